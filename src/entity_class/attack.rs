@@ -0,0 +1,214 @@
+use crate::entity_class::enemy::{Enemy, EnemyState};
+use crate::entity_class::health::Damaged;
+use crate::entity_class::patrol_path::PatrolFollower;
+use crate::entity_class::projectile::{projectile_atlas, spawn_projectile, Projectile};
+use crate::entity_class::vision::VisionCone;
+use crate::map::tile_grid::TileGrid;
+use crate::tags::Player;
+use crate::ticks::GameTime;
+use crate::GameLayer;
+use bevy::prelude::*;
+
+/// How an enemy picks fights, and who it is currently fighting
+pub struct EnemyAttack {
+    /// Players closer than this are noticed, unless the enemy has a vision cone
+    pub sight_range: f32,
+    /// Targets further away than this, or out of sight, are given up on
+    pub give_up_range: f32,
+    /// Targets this close are hit directly
+    pub melee_range: f32,
+    pub melee_damage: i32,
+    /// Targets out of melee reach but within this range are shot at
+    pub fire_range: f32,
+    pub projectile_damage: i32,
+    pub projectile_speed: f32,
+    /// Time between two attacks
+    pub cooldown: Timer,
+    pub target: Option<Entity>,
+}
+
+impl Default for EnemyAttack {
+    fn default() -> Self {
+        EnemyAttack {
+            sight_range: 200.,
+            give_up_range: 300.,
+            melee_range: 48.,
+            melee_damage: 10,
+            fire_range: 160.,
+            projectile_damage: 20,
+            projectile_speed: 150.,
+            cooldown: Timer::from_seconds(1., false),
+            target: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AttackMove {
+    Melee,
+    Fire,
+    Chase,
+}
+
+impl EnemyAttack {
+    /// What to do about a target `distance` away
+    pub fn attack_move(&self, distance: f32) -> AttackMove {
+        if distance <= self.melee_range {
+            AttackMove::Melee
+        } else if distance <= self.fire_range {
+            AttackMove::Fire
+        } else {
+            AttackMove::Chase
+        }
+    }
+}
+
+/// Enemies chasing a target stop once they are this fraction of their melee range away
+pub const CHASE_STOP: f32 = 0.75;
+
+/// How long enemy projectiles fly before fizzling out
+const PROJECTILE_LIFETIME: f32 = 2.;
+
+/// The state an enemy goes back to once it loses its target
+fn fallback_state(patrol: Option<&PatrolFollower>) -> EnemyState {
+    match patrol {
+        Some(patrol) if patrol.path.is_some() && !patrol.finished => EnemyState::Patrol,
+        _ => EnemyState::Idle,
+    }
+}
+
+/// Enemies lock on to the nearest visible player in range, or in their vision cone, and drop
+/// back to their usual business once the target is gone, too far away or behind a wall
+pub fn acquire_targets(
+    grid: Option<Res<TileGrid>>,
+    mut enemies: Query<(
+        &mut Enemy,
+        &mut EnemyAttack,
+        &Transform,
+        Option<&PatrolFollower>,
+        Option<&VisionCone>,
+    )>,
+    players: Query<(Entity, &Transform), With<Player>>,
+) {
+    let grid = grid.as_deref();
+
+    for (mut enemy, mut attack, transform, patrol, cone) in enemies.iter_mut() {
+        let position = transform.translation;
+        let visible = |target: &Transform, range: f32| {
+            target.translation.distance(position) <= range
+                && grid.is_none_or(|grid| grid.line_of_sight_world(position, target.translation))
+        };
+
+        // Hang on to the current target for as long as possible before looking for another
+        let kept = attack
+            .target
+            .and_then(|target| players.get(target).ok())
+            .filter(|(_, target)| visible(*target, attack.give_up_range))
+            .map(|(eid, _)| eid);
+        let target = kept.or_else(|| {
+            players
+                .iter()
+                .filter(|(eid, target)| match cone {
+                    Some(cone) => cone.seen.contains(eid),
+                    None => visible(*target, attack.sight_range),
+                })
+                .map(|(eid, target)| (eid, target.translation.distance(position)))
+                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+                .map(|(eid, _)| eid)
+        });
+
+        match (target, &enemy.state) {
+            (Some(_), EnemyState::Attack) => {}
+            (Some(_), _) => enemy.state = EnemyState::Attack,
+            (None, EnemyState::Attack) => enemy.state = fallback_state(patrol),
+            (None, _) => {}
+        }
+        if attack.target != target {
+            attack.target = target;
+        }
+    }
+}
+
+/// Attacking enemies hit targets in reach and shoot at those further away
+pub fn enemy_attack(
+    mut c: Commands,
+    time: Res<GameTime>,
+    asset_server: Res<AssetServer>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut damaged: EventWriter<Damaged>,
+    mut enemies: Query<(&Enemy, &mut EnemyAttack, &Transform)>,
+    targets: Query<&Transform, With<Player>>,
+) {
+    for (enemy, mut attack, transform) in enemies.iter_mut() {
+        attack.cooldown.tick(time.delta());
+        if !matches!(enemy.state, EnemyState::Attack) || !attack.cooldown.finished() {
+            continue;
+        }
+
+        let (target, target_transform) = match attack
+            .target
+            .and_then(|target| targets.get(target).ok().map(|t| (target, t)))
+        {
+            Some(target) => target,
+            None => continue,
+        };
+
+        let to_target = target_transform.translation - transform.translation;
+        match attack.attack_move(to_target.truncate().length()) {
+            AttackMove::Melee => damaged.send(Damaged {
+                damage: attack.melee_damage,
+                entity: target,
+            }),
+            AttackMove::Fire => {
+                let texture_atlas = projectile_atlas(&asset_server, &mut texture_atlases);
+                spawn_projectile(
+                    &mut c,
+                    texture_atlas,
+                    transform.translation,
+                    to_target.truncate().normalize().extend(0.) * attack.projectile_speed,
+                    Projectile {
+                        damage: attack.projectile_damage,
+                        ..Default::default()
+                    },
+                    &[GameLayer::World, GameLayer::Player],
+                    PROJECTILE_LIFETIME,
+                );
+            }
+            AttackMove::Chase => continue,
+        }
+        attack.cooldown.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attack_move_depends_on_distance() {
+        let attack = EnemyAttack::default();
+        assert_eq!(attack.attack_move(0.), AttackMove::Melee);
+        assert_eq!(attack.attack_move(attack.melee_range), AttackMove::Melee);
+        assert_eq!(
+            attack.attack_move(attack.melee_range + 1.),
+            AttackMove::Fire
+        );
+        assert_eq!(
+            attack.attack_move(attack.fire_range + 1.),
+            AttackMove::Chase
+        );
+    }
+
+    #[test]
+    fn lost_targets_fall_back_to_patrolling_paths() {
+        let mut patrol = PatrolFollower::new(None, Default::default());
+        assert!(matches!(fallback_state(None), EnemyState::Idle));
+        assert!(matches!(fallback_state(Some(&patrol)), EnemyState::Idle));
+
+        patrol.path = Some(Entity::new(1));
+        assert!(matches!(fallback_state(Some(&patrol)), EnemyState::Patrol));
+
+        patrol.finished = true;
+        assert!(matches!(fallback_state(Some(&patrol)), EnemyState::Idle));
+    }
+}
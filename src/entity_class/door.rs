@@ -1,16 +1,233 @@
+use crate::entity_class::enemy::{Enemy, EnemyState};
 use crate::entity_class::indexer::ParseFields;
+use crate::entity_class::key::Keys;
+use crate::input::Action;
+use crate::map::map_colliders::wall_collider;
+use crate::map::map_loader::MapEntity;
+use crate::map::tile_grid::{TileCell, TileGrid};
+use crate::map::utils::MapCoords;
+use crate::tags::{Player, WorldType};
 use bevy::prelude::*;
-use serde_json::Value;
-use std::collections::HashMap;
+use heron::SensorShape;
+use serde::Deserialize;
 
 pub const DOOR_ID: &str = "Door";
-pub struct Door;
-
-impl ParseFields for Door {
-    fn parse(fields: &HashMap<String, Option<Value>>) -> anyhow::Result<Self>
-    where
-        Self: Sized,
-    {
-        Ok(Door {})
+
+/// Door frames in the map tileset
+const LOCKED_FRAME: u32 = 4;
+const CLOSED_FRAME: u32 = 5;
+const OPEN_FRAME: u32 = 6;
+
+/// How close, in tiles, a creature has to be to open a door
+const INTERACT_RANGE: f32 = 1.5;
+
+#[derive(Deserialize, Debug)]
+pub struct Door {
+    #[serde(rename = "Open", default)]
+    pub open: bool,
+    #[serde(rename = "Locked", default)]
+    pub locked: bool,
+    /// Name of the key that unlocks the door, a locked door without one never opens
+    #[serde(rename = "Key", default)]
+    pub key: Option<String>,
+    /// Opened by an enemy passing through, closes again once nobody is in the way
+    #[serde(skip)]
+    pub opened_by_enemy: bool,
+}
+
+impl ParseFields for Door {}
+
+impl Door {
+    pub fn frame(&self) -> u32 {
+        if self.open {
+            OPEN_FRAME
+        } else if self.locked {
+            LOCKED_FRAME
+        } else {
+            CLOSED_FRAME
+        }
+    }
+
+    /// Opens or closes the door, unlocking it first if `keys` holds its key.
+    /// Returns whether anything changed
+    pub fn interact(&mut self, keys: &Keys) -> bool {
+        if self.locked {
+            match &self.key {
+                Some(key) if keys.has(key) => self.locked = false,
+                _ => return false,
+            }
+        }
+        self.open = !self.open;
+        self.opened_by_enemy = false;
+        true
+    }
+}
+
+pub fn add_door_colliders(mut c: Commands, doors: Query<(Entity, &MapEntity), Added<Door>>) {
+    for (eid, entity) in doors.iter() {
+        c.entity(eid)
+            .insert_bundle(wall_collider((entity.size / 2.).extend(0.)));
+    }
+}
+
+type DoorQuery<'a> = (
+    Entity,
+    &'a Door,
+    ChangeTrackers<Door>,
+    &'a Transform,
+    Option<&'a mut TextureAtlasSprite>,
+);
+
+/// Keeps the collider, sprite and tile grid in step with the door's state.
+/// Open doors stay sensors so they can be closed again without respawning the collider
+pub fn update_doors(
+    mut c: Commands,
+    mut grid: Option<ResMut<TileGrid>>,
+    mut doors: Query<DoorQuery>,
+) {
+    // The grid is rebuilt from map tiles, so doors have to be put back in afterwards
+    let grid_rebuilt = grid.as_ref().is_some_and(|grid| grid.is_added());
+    for (eid, door, tracker, transform, sprite) in doors.iter_mut() {
+        if !tracker.is_changed() && !grid_rebuilt {
+            continue;
+        }
+        if door.open {
+            c.entity(eid).insert(SensorShape);
+        } else {
+            c.entity(eid).remove::<SensorShape>();
+        }
+        if let Some(mut sprite) = sprite {
+            sprite.index = door.frame();
+        }
+        if let Some(grid) = grid.as_mut() {
+            let cell = grid.coords.world_to_grid(transform.translation);
+            grid.set(
+                cell,
+                TileCell {
+                    entity: eid,
+                    // Locked doors are as good as walls, even to paths
+                    world_type: if door.open {
+                        WorldType::Air
+                    } else if door.locked {
+                        WorldType::Wall
+                    } else {
+                        WorldType::Door
+                    },
+                },
+            );
+        }
+    }
+}
+
+fn in_reach(coords: &MapCoords, from: Vec3, door: Vec3) -> bool {
+    from.truncate().distance(door.truncate()) <= coords.tile_size().x * INTERACT_RANGE
+}
+
+pub fn player_opens_doors(
+    input: Res<Input<Action>>,
+    coords: Option<Res<MapCoords>>,
+    player: Query<(&Transform, &Keys), With<Player>>,
+    mut doors: Query<(&mut Door, &Transform)>,
+) {
+    if !input.just_pressed(Action::Interact) {
+        return;
+    }
+    let coords = match coords {
+        Some(coords) => coords,
+        None => return,
+    };
+    for (player_transform, keys) in player.iter() {
+        let nearest = doors
+            .iter_mut()
+            .filter(|(_, transform)| {
+                in_reach(&coords, player_transform.translation, transform.translation)
+            })
+            .min_by(|(_, a), (_, b)| {
+                let a = a.translation.distance(player_transform.translation);
+                let b = b.translation.distance(player_transform.translation);
+                a.partial_cmp(&b).unwrap()
+            });
+        if let Some((mut door, _)) = nearest {
+            door.interact(keys);
+        }
+    }
+}
+
+/// Enemies on the move open closed doors in their way, but can't get through locked ones.
+/// Idle enemies aren't going anywhere, so they leave doors be. Doors an enemy opened close
+/// behind it once no creature is left in reach, doors the player opened are left to them
+pub fn enemies_open_doors(
+    coords: Option<Res<MapCoords>>,
+    enemies: Query<(&Enemy, &Transform)>,
+    creatures: Query<&Transform, Or<(With<Enemy>, With<Player>)>>,
+    mut doors: Query<(&mut Door, &Transform)>,
+) {
+    let coords = match coords {
+        Some(coords) => coords,
+        None => return,
+    };
+    for (mut door, transform) in doors.iter_mut() {
+        let door_position = transform.translation;
+        if !door.open && !door.locked {
+            let opening = enemies.iter().any(|(enemy, enemy_transform)| {
+                !matches!(enemy.state, EnemyState::Idle)
+                    && in_reach(&coords, enemy_transform.translation, door_position)
+            });
+            if opening {
+                door.open = true;
+                door.opened_by_enemy = true;
+            }
+        } else if door.opened_by_enemy
+            && !creatures
+                .iter()
+                .any(|creature| in_reach(&coords, creature.translation, door_position))
+        {
+            door.open = false;
+            door.opened_by_enemy = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn door(locked: bool, key: Option<&str>) -> Door {
+        Door {
+            open: false,
+            locked,
+            key: key.map(str::to_string),
+            opened_by_enemy: false,
+        }
+    }
+
+    #[test]
+    fn unlocked_doors_toggle() {
+        let mut door = door(false, None);
+        assert!(door.interact(&Keys::default()));
+        assert!(door.open);
+        assert!(door.interact(&Keys::default()));
+        assert!(!door.open);
+    }
+
+    #[test]
+    fn locked_doors_need_their_key() {
+        let mut door = door(true, Some("Red"));
+        let mut keys = Keys::default();
+        keys.add("Blue".to_string());
+        assert!(!door.interact(&keys));
+        assert_eq!(door.frame(), LOCKED_FRAME);
+
+        keys.add("Red".to_string());
+        assert!(door.interact(&keys));
+        assert!(door.open && !door.locked);
+        assert_eq!(door.frame(), OPEN_FRAME);
+    }
+
+    #[test]
+    fn locked_doors_without_a_key_stay_shut() {
+        let mut keys = Keys::default();
+        keys.add("Red".to_string());
+        assert!(!door(true, None).interact(&keys));
     }
 }
@@ -1,7 +1,13 @@
-use crate::entity_class::patrol_path::PatrolPath;
+use crate::entity_class::patrol_path::{PatrolFollower, PatrolPath};
 
+use crate::entity_class::attack::{EnemyAttack, CHASE_STOP};
 use crate::entity_class::creature::Creature;
 use crate::entity_class::movement::{LastMovementDirection, MovementDirection};
+use crate::entity_class::path_follower::{PathFollower, WAYPOINT_REACHED};
+use crate::entity_class::vision::VisionCone;
+use crate::map::map_loader::LevelScoped;
+use crate::rng::EntityRng;
+use crate::tags::Player;
 use crate::GameLayer;
 use bevy::prelude::*;
 use bevy_inspector_egui::Inspectable;
@@ -28,8 +34,10 @@ impl Default for Enemy {
 
 #[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
 pub enum EnemyFunctions {
+    Target,
     Move,
     ChangeState,
+    PlanPath,
 }
 
 #[derive(Inspectable, Debug)]
@@ -40,6 +48,7 @@ pub enum EnemyState {
     Wander,
 }
 
+// Attack is only ever entered by spotting a target, see `attack::acquire_targets`
 impl Distribution<EnemyState> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> EnemyState {
         use EnemyState::*;
@@ -52,11 +61,12 @@ impl Distribution<EnemyState> for Standard {
 }
 
 pub fn spawn_enemy(
-    mut c: &mut Commands,
+    c: &mut Commands,
     assets: &Res<AssetServer>,
-    mut texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
+    texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
     transform: &Transform,
-) {
+    patrol: PatrolFollower,
+) -> Entity {
     let enemy_sprite = assets.load("enemy.spritemap.png");
     let texture_atlas = TextureAtlas::from_grid(enemy_sprite, Vec2::new(64., 64.), 4, 1);
     let texture_atlas_handle = texture_atlases.add(texture_atlas);
@@ -76,6 +86,11 @@ pub fn spawn_enemy(
             move_mod: -1,
         })
         .insert(Creature)
+        .insert(EnemyAttack::default())
+        .insert(VisionCone::default())
+        .insert(patrol)
+        .insert(PathFollower::new(28.))
+        .insert(LevelScoped)
         .insert(Velocity::from_linear(Vec3::default()))
         .insert(RigidBody::Dynamic)
         .insert(RotationConstraints::lock())
@@ -88,16 +103,15 @@ pub fn spawn_enemy(
             CollisionLayers::none()
                 .with_group(GameLayer::Enemy)
                 .with_masks(&[GameLayer::World, GameLayer::Player, GameLayer::Projectile]),
-        );
+        )
+        .id()
 }
 
 // rand_update_enemy_state iterates over all enemies on the board and
 // randomly determines if the enemy state should change from {Idle} to {Patrol}
 // and vice versa
-pub fn rand_update_enemy_state(mut enemies: Query<&mut Enemy>) {
-    let mut rng = rand::thread_rng();
-
-    for mut enemy in enemies.iter_mut() {
+pub fn rand_update_enemy_state(mut enemies: Query<(&mut Enemy, &mut EntityRng)>) {
+    for (mut enemy, mut rng) in enemies.iter_mut() {
         match &enemy.state {
             EnemyState::Idle => {
                 let chance_to_change = rng.gen_range(0..33);
@@ -122,38 +136,79 @@ pub fn rand_update_enemy_state(mut enemies: Query<&mut Enemy>) {
     }
 }
 
-pub fn move_down(mut q: Query<(&mut Velocity, &mut Enemy, &Transform), With<Enemy>>) {
-    let mut rng = rand::thread_rng();
-
-    for (mut real_vel, mut enemy, transform) in q.iter_mut() {
-        let mut vel = real_vel.clone();
+pub fn move_enemies(
+    mut q: Query<(
+        &mut Velocity,
+        &Enemy,
+        &Transform,
+        &mut PatrolFollower,
+        &mut PathFollower,
+        &mut EntityRng,
+        Option<&EnemyAttack>,
+    )>,
+    paths: Query<&PatrolPath>,
+    targets: Query<&Transform, With<Player>>,
+) {
+    for (mut real_vel, enemy, transform, mut patrol, mut follower, mut rng, attack) in q.iter_mut()
+    {
+        let mut vel = *real_vel;
         let move_speed = 10.;
         let min_speed = 0.01;
         let max_speed = 100.;
         let friction = 0.95;
+        let position = transform.translation.truncate();
 
-        match &enemy.state {
+        // Where the path follower should take the enemy, if anywhere
+        let goal = match &enemy.state {
             EnemyState::Patrol => {
-                if transform.translation.y < enemy.start_loc.translation.y - 200. {
-                    enemy.move_mod = 1
-                } else if transform.translation.y >= enemy.start_loc.translation.y {
-                    enemy.move_mod = -1
+                let waypoints = match patrol.path.and_then(|path| paths.get(path).ok()) {
+                    Some(path) if !patrol.finished => Some(&path.waypoints),
+                    _ => None,
+                };
+                match waypoints.map(|waypoints| (waypoints.get(patrol.waypoint), waypoints.len())) {
+                    Some((Some(target), len)) => {
+                        if target.distance(position) < WAYPOINT_REACHED {
+                            patrol.advance(len);
+                            None
+                        } else {
+                            Some(*target)
+                        }
+                    }
+                    Some((None, _)) => {
+                        // The path changed under us, start it over
+                        patrol.waypoint = 0;
+                        None
+                    }
+                    None => None,
                 }
-
-                vel.linear.y += move_speed * enemy.move_mod as f32;
             }
             EnemyState::Wander => {
                 vel.linear += Vec3::new(
                     rng.gen_range(-move_speed..move_speed),
                     rng.gen_range(-move_speed..move_speed),
                     0.,
-                )
+                );
+                None
+            }
+            EnemyState::Attack => attack.and_then(|attack| {
+                let target = targets.get(attack.target?).ok()?.translation.truncate();
+                // Close in, but leave room to swing
+                (target.distance(position) > attack.melee_range * CHASE_STOP).then_some(target)
+            }),
+            EnemyState::Idle => {
+                // Head back to where we started when there is nothing else to do
+                let post = enemy.start_loc.translation.truncate();
+                Some(post).filter(|post| post.distance(position) > POST_REACHED)
             }
-            _ => {}
         };
+        match goal {
+            Some(goal) => follower.go_to(goal),
+            None => follower.stop(),
+        }
 
         // Clamp velocity to MAX
-        vel.linear
+        vel.linear = vel
+            .linear
             .clamp(Vec3::splat(-max_speed), Vec3::splat(max_speed));
 
         // Apply velocity degradation due to friction
@@ -174,6 +229,39 @@ pub fn move_down(mut q: Query<(&mut Velocity, &mut Enemy, &Transform), With<Enem
     }
 }
 
-pub fn find_nearest_patrol_path(mut q: Query<(&mut PatrolPath, &Transform)>) {
-    for (mut path, location) in q.iter_mut() {}
+/// How close an idle enemy has to be to where it started to stop heading back
+const POST_REACHED: f32 = 16.;
+
+/// Attaches followers to the path they are linked to, or to the one with the nearest waypoint
+pub fn find_nearest_patrol_path(
+    mut followers: Query<(&mut PatrolFollower, &Transform)>,
+    paths: Query<(Entity, &PatrolPath)>,
+) {
+    for (mut follower, location) in followers.iter_mut() {
+        if follower.path.is_some() {
+            continue;
+        }
+
+        let position = location.translation.truncate();
+        let path = match &follower.link {
+            Some(link) => paths
+                .iter()
+                .find(|(_, path)| path.name.as_ref() == Some(link))
+                .map(|(eid, _)| eid),
+            None => paths
+                .iter()
+                .flat_map(|(eid, path)| {
+                    path.waypoints
+                        .iter()
+                        .map(move |waypoint| (eid, waypoint.distance(position)))
+                })
+                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+                .map(|(eid, _)| eid),
+        };
+
+        if let Some(path) = path {
+            follower.path = Some(path);
+            follower.waypoint = 0;
+        }
+    }
 }
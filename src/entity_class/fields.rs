@@ -0,0 +1,457 @@
+use bevy::prelude::*;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Expected, IntoDeserializer, MapAccess, SeqAccess,
+    Unexpected, Visitor,
+};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Why an entity field could not be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    /// LDtk identifier of the field, nested fields and array items are joined with `.`
+    pub field: String,
+    pub kind: FieldErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldErrorKind {
+    Missing,
+    WrongType {
+        expected: String,
+        actual: String,
+    },
+    UnknownEnumValue {
+        value: String,
+        expected: Vec<String>,
+    },
+    OutOfRange {
+        value: String,
+        expected: String,
+    },
+    Invalid(String),
+}
+
+impl FieldError {
+    fn in_field(mut self, field: &str) -> Self {
+        self.field = if self.field.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", field, self.field)
+        };
+        self
+    }
+
+    fn new(kind: FieldErrorKind) -> Self {
+        FieldError {
+            field: String::new(),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            FieldErrorKind::Missing => write!(f, "missing field {}", self.field),
+            FieldErrorKind::WrongType { expected, actual } => write!(
+                f,
+                "field {} should be {} but is {}",
+                self.field, expected, actual
+            ),
+            FieldErrorKind::UnknownEnumValue { value, expected } => write!(
+                f,
+                "field {} has unknown value {}, expected one of {}",
+                self.field,
+                value,
+                expected.join(", ")
+            ),
+            FieldErrorKind::OutOfRange { value, expected } => write!(
+                f,
+                "field {} is out of range, {} is not {}",
+                self.field, value, expected
+            ),
+            FieldErrorKind::Invalid(reason) => write!(f, "field {} {}", self.field, reason),
+        }
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+impl de::Error for FieldError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        FieldError::new(FieldErrorKind::Invalid(msg.to_string()))
+    }
+
+    fn invalid_type(unexp: Unexpected, exp: &dyn Expected) -> Self {
+        FieldError::new(FieldErrorKind::WrongType {
+            expected: exp.to_string(),
+            actual: unexp.to_string(),
+        })
+    }
+
+    fn invalid_value(unexp: Unexpected, exp: &dyn Expected) -> Self {
+        FieldError::new(FieldErrorKind::OutOfRange {
+            value: unexp.to_string(),
+            expected: exp.to_string(),
+        })
+    }
+
+    fn unknown_variant(variant: &str, expected: &'static [&'static str]) -> Self {
+        FieldError::new(FieldErrorKind::UnknownEnumValue {
+            value: variant.to_string(),
+            expected: expected.iter().map(|v| v.to_string()).collect(),
+        })
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        FieldError {
+            field: field.to_string(),
+            kind: FieldErrorKind::Missing,
+        }
+    }
+}
+
+/// Deserializes an entity's LDtk field instances into `T`, field identifiers map to struct fields
+/// through `#[serde(rename = "...")]`.
+/// Fields left empty in LDtk are treated as missing, so `Option` fields become `None` and
+/// `#[serde(default)]` fields take their default
+pub fn from_fields<T: DeserializeOwned>(
+    fields: &HashMap<String, Option<Value>>,
+) -> Result<T, FieldError> {
+    let object = fields
+        .iter()
+        .filter_map(|(name, value)| match value {
+            Some(Value::Null) | None => None,
+            Some(value) => Some((name.clone(), value.clone())),
+        })
+        .collect();
+    T::deserialize(ValueDeserializer(Value::Object(object)))
+}
+
+/// Deserializes JSON values with `FieldError`s, so failures keep their kind and field name
+struct ValueDeserializer(Value);
+
+fn unexpected(value: &Value) -> Unexpected<'_> {
+    match value {
+        Value::Null => Unexpected::Unit,
+        Value::Bool(b) => Unexpected::Bool(*b),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => Unexpected::Unsigned(u),
+            (_, Some(i)) => Unexpected::Signed(i),
+            _ => Unexpected::Float(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => Unexpected::Str(s),
+        Value::Array(_) => Unexpected::Seq,
+        Value::Object(_) => Unexpected::Map,
+    }
+}
+
+impl<'de> IntoDeserializer<'de, FieldError> for ValueDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = FieldError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FieldError> {
+        match self.0 {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+                (Some(u), _) => visitor.visit_u64(u),
+                (_, Some(i)) => visitor.visit_i64(i),
+                _ => visitor.visit_f64(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) => visitor.visit_string(s),
+            Value::Array(items) => visitor.visit_seq(ValueSeq {
+                items: items.into_iter().enumerate(),
+            }),
+            Value::Object(entries) => visitor.visit_map(ValueMap {
+                entries: entries.into_iter(),
+                value: None,
+            }),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FieldError> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    // LDtk stores enum values as their name
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, FieldError> {
+        match self.0 {
+            Value::String(s) => visitor.visit_enum(s.into_deserializer()),
+            value => Err(de::Error::invalid_type(
+                unexpected(&value),
+                &"an enum value",
+            )),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, FieldError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct ValueSeq {
+    items: std::iter::Enumerate<std::vec::IntoIter<Value>>,
+}
+
+impl<'de> SeqAccess<'de> for ValueSeq {
+    type Error = FieldError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, FieldError> {
+        match self.items.next() {
+            Some((i, item)) => seed
+                .deserialize(ValueDeserializer(item))
+                .map(Some)
+                .map_err(|e| e.in_field(&i.to_string())),
+            None => Ok(None),
+        }
+    }
+}
+
+struct ValueMap {
+    entries: serde_json::map::IntoIter,
+    value: Option<(String, Value)>,
+}
+
+impl<'de> MapAccess<'de> for ValueMap {
+    type Error = FieldError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, FieldError> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some((key.clone(), value));
+                seed.deserialize(key.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, FieldError> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value requested before its key"))?;
+        seed.deserialize(ValueDeserializer(value))
+            .map_err(|e| e.in_field(&key))
+    }
+}
+
+/// A LDtk `Point` field, in grid cells
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GridPoint {
+    pub cx: i32,
+    pub cy: i32,
+}
+
+impl From<GridPoint> for IVec2 {
+    fn from(point: GridPoint) -> Self {
+        IVec2::new(point.cx, point.cy)
+    }
+}
+
+/// A LDtk `Color` field, stored by LDtk as `#rrggbb`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FieldColor(pub Color);
+
+impl<'de> Deserialize<'de> for FieldColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let digits = hex.trim_start_matches('#');
+        let rgb = u32::from_str_radix(digits, 16)
+            .ok()
+            .filter(|_| digits.len() == 6)
+            .ok_or_else(|| de::Error::invalid_value(Unexpected::Str(&hex), &"a #rrggbb colour"))?;
+
+        Ok(FieldColor(Color::rgb_u8(
+            (rgb >> 16) as u8,
+            (rgb >> 8) as u8,
+            rgb as u8,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    enum Character {
+        Player,
+        Enemy,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Everything {
+        #[serde(rename = "Count")]
+        count: i32,
+        #[serde(rename = "Character")]
+        character: Character,
+        #[serde(rename = "Path")]
+        path: Vec<GridPoint>,
+        #[serde(rename = "Tint")]
+        tint: FieldColor,
+        #[serde(rename = "Label")]
+        label: Option<String>,
+        #[serde(rename = "Speed", default)]
+        speed: f32,
+    }
+
+    fn fields(values: Value) -> HashMap<String, Option<Value>> {
+        values
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(name, value)| (name.clone(), Some(value.clone())))
+            .collect()
+    }
+
+    #[test]
+    fn parses_every_field_kind() {
+        let parsed: Everything = from_fields(&fields(json!({
+            "Count": 3,
+            "Character": "Enemy",
+            "Path": [{ "cx": 1, "cy": 2 }, { "cx": 3, "cy": 4 }],
+            "Tint": "#ff8000",
+            "Label": "guard",
+            "Speed": 1.5,
+        })))
+        .unwrap();
+
+        assert_eq!(parsed.count, 3);
+        assert_eq!(parsed.character, Character::Enemy);
+        assert_eq!(
+            parsed
+                .path
+                .iter()
+                .map(|p| IVec2::from(*p))
+                .collect::<Vec<_>>(),
+            vec![IVec2::new(1, 2), IVec2::new(3, 4)]
+        );
+        assert_eq!(parsed.tint.0, Color::rgb_u8(255, 128, 0));
+        assert_eq!(parsed.label.as_deref(), Some("guard"));
+        assert_eq!(parsed.speed, 1.5);
+    }
+
+    #[test]
+    fn empty_fields_fall_back_to_none_and_defaults() {
+        let mut fields = fields(json!({
+            "Count": 1,
+            "Character": "Player",
+            "Path": [],
+            "Tint": "#000000",
+            "Label": null,
+        }));
+        fields.insert("Speed".into(), None);
+
+        let parsed: Everything = from_fields(&fields).unwrap();
+        assert_eq!(parsed.label, None);
+        assert_eq!(parsed.speed, 0.);
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let base = json!({ "Count": 1, "Character": "Player", "Path": [], "Tint": "#000000" });
+        assert!(from_fields::<Everything>(&fields(base.clone())).is_ok());
+
+        let mut unknown_enum = base.clone();
+        unknown_enum["Character"] = json!("Dragon");
+        assert!(from_fields::<Everything>(&fields(unknown_enum)).is_err());
+
+        let mut bad_colour = base.clone();
+        bad_colour["Tint"] = json!("red");
+        assert!(from_fields::<Everything>(&fields(bad_colour)).is_err());
+
+        let mut missing = base;
+        missing.as_object_mut().unwrap().remove("Count");
+        assert!(from_fields::<Everything>(&fields(missing)).is_err());
+    }
+
+    fn error(values: Value) -> FieldError {
+        let mut base = json!({ "Count": 1, "Character": "Player", "Path": [], "Tint": "#000000" });
+        for (name, value) in values.as_object().unwrap() {
+            match value {
+                Value::Null => base.as_object_mut().unwrap().remove(name),
+                value => base
+                    .as_object_mut()
+                    .unwrap()
+                    .insert(name.clone(), value.clone()),
+            };
+        }
+        from_fields::<Everything>(&fields(base)).unwrap_err()
+    }
+
+    #[test]
+    fn errors_name_the_field_and_problem() {
+        let missing = error(json!({ "Count": null }));
+        assert_eq!(missing.field, "Count");
+        assert_eq!(missing.kind, FieldErrorKind::Missing);
+
+        let wrong_type = error(json!({ "Count": "three" }));
+        assert_eq!(wrong_type.field, "Count");
+        assert!(matches!(
+            wrong_type.kind,
+            FieldErrorKind::WrongType { ref actual, .. } if actual.contains("three")
+        ));
+
+        let unknown = error(json!({ "Character": "Dragon" }));
+        assert_eq!(unknown.field, "Character");
+        assert_eq!(
+            unknown.kind,
+            FieldErrorKind::UnknownEnumValue {
+                value: "Dragon".into(),
+                expected: vec!["Player".into(), "Enemy".into()],
+            }
+        );
+
+        let out_of_range = error(json!({ "Count": 5_000_000_000u64 }));
+        assert_eq!(out_of_range.field, "Count");
+        assert!(matches!(
+            out_of_range.kind,
+            FieldErrorKind::OutOfRange { .. }
+        ));
+    }
+
+    #[test]
+    fn nested_errors_have_a_path() {
+        let nested = error(json!({ "Path": [{ "cx": 1, "cy": 1 }, { "cx": 2 }] }));
+        assert_eq!(nested.field, "Path.1.cy");
+        assert_eq!(nested.kind, FieldErrorKind::Missing);
+    }
+}
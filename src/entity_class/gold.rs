@@ -1,27 +1,87 @@
-use crate::entity_class::indexer::{IndexingError, ParseFields};
-use anyhow::anyhow;
+use crate::entity_class::indexer::ParseFields;
+use crate::map::map_loader::MapEntity;
+use crate::ui::Score;
+use crate::GameLayer;
 use bevy::prelude::*;
-use serde_json::Value;
-use std::collections::HashMap;
+use heron::{CollisionEvent, CollisionLayers, CollisionShape, RigidBody};
+use serde::Deserialize;
+use std::collections::HashSet;
 
+#[derive(Deserialize)]
 pub struct Gold {
-    value: i32,
+    #[serde(rename = "Value")]
+    pub value: u32,
 }
 
 pub const GOLD_ID: &str = "Gold";
 
-impl ParseFields for Gold {
-    fn parse(fields: &HashMap<String, Option<Value>>) -> anyhow::Result<Self> {
-        let value = fields
-            .get("Value")
-            .ok_or_else(|| IndexingError::InvalidParse)?;
-        let value_number = match value {
-            Some(Value::Number(n)) => n.as_i64().unwrap(),
-            _ => Err(IndexingError::InvalidParse)?,
-        };
-
-        Ok(Gold {
-            value: value_number as i32,
-        })
+impl ParseFields for Gold {}
+
+/// Sent when the player picks up gold, after its value has been added to the `Score`
+pub struct GoldCollected {
+    pub value: u32,
+    pub position: Vec3,
+}
+
+pub fn add_gold_sensors(mut c: Commands, gold: Query<(Entity, &MapEntity), Added<Gold>>) {
+    for (eid, entity) in gold.iter() {
+        c.entity(eid)
+            .insert_bundle(pickup_sensor((entity.size / 2.).extend(0.)));
+    }
+}
+
+/// A sensor the player can walk over to pick something up
+pub fn pickup_sensor(half_extends: Vec3) -> (RigidBody, CollisionShape, CollisionLayers) {
+    (
+        RigidBody::Sensor,
+        CollisionShape::Cuboid {
+            half_extends,
+            border_radius: None,
+        },
+        CollisionLayers::none()
+            .with_group(GameLayer::Pickup)
+            .with_mask(GameLayer::Player),
+    )
+}
+
+/// The pickup the player started touching, if the event is about one
+pub fn picked_up(event: &CollisionEvent) -> Option<Entity> {
+    if !event.is_started() {
+        return None;
+    }
+    let (eid_1, eid_2) = event.rigid_body_entities();
+    let (layer_1, layer_2) = event.collision_layers();
+
+    if layer_1.contains_group(GameLayer::Player) && layer_2.contains_group(GameLayer::Pickup) {
+        Some(eid_2)
+    } else if layer_1.contains_group(GameLayer::Pickup) && layer_2.contains_group(GameLayer::Player)
+    {
+        Some(eid_1)
+    } else {
+        None
+    }
+}
+
+pub fn collect_gold(
+    mut c: Commands,
+    mut score: ResMut<Score>,
+    mut collected: EventWriter<GoldCollected>,
+    mut collisions: EventReader<CollisionEvent>,
+    gold: Query<(&Gold, &Transform)>,
+) {
+    // Touching a pile twice in one frame still only picks it up once
+    let picked = collisions
+        .iter()
+        .filter_map(picked_up)
+        .collect::<HashSet<_>>();
+    for gold_id in picked {
+        if let Ok((gold, transform)) = gold.get(gold_id) {
+            score.0 += gold.value;
+            collected.send(GoldCollected {
+                value: gold.value,
+                position: transform.translation,
+            });
+            c.entity(gold_id).despawn();
+        }
     }
 }
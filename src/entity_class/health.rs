@@ -7,8 +7,14 @@ pub struct Health {
 }
 
 pub struct Damaged {
-    pub(crate) damage: i32,
-    pub(crate) entity: Entity,
+    pub damage: i32,
+    pub entity: Entity,
+}
+
+impl Health {
+    pub fn value(&self) -> i32 {
+        self.value
+    }
 }
 
 impl Default for Health {
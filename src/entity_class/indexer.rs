@@ -1,73 +1,205 @@
-use crate::entity_class::door::{Door, DOOR_ID};
-use crate::entity_class::gold::{Gold, GOLD_ID};
-use crate::entity_class::patrol_path::{PatrolPath, PATROL_PATH_ID};
-use crate::entity_class::start_location::{StartLocation, START_LOCATION_ID};
+use crate::entity_class::fields::{from_fields, FieldError, FieldErrorKind};
+use crate::map::load_error::{report_load_error, MapLoadError};
 use crate::map::map_loader::MapEntity;
-use anyhow::{anyhow, Error};
 use bevy::ecs::component::Component;
 use bevy::prelude::*;
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+/// Where to find an entity in the editor.
+/// LDtk 0.9 projects have no entity iids, the definition uid and grid cell stand in for one
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityContext {
+    pub identifier: String,
+    pub def_uid: i32,
+    pub level: String,
+    pub layer: String,
+    pub grid: IVec2,
+}
+
+impl From<&MapEntity> for EntityContext {
+    fn from(entity: &MapEntity) -> Self {
+        EntityContext {
+            identifier: entity.name.clone(),
+            def_uid: entity.def_uid,
+            level: entity.level.clone(),
+            layer: entity.layer.clone(),
+            grid: entity.grid_pos.as_i32(),
+        }
+    }
+}
+
+impl fmt::Display for EntityContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (def uid {}) at grid {},{} on layer {} of level {}",
+            self.identifier, self.def_uid, self.grid.x, self.grid.y, self.layer, self.level
+        )
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum IndexingError {
-    // #[error("No known type to parse to")]
-    // NoKnownType,
-    #[error("Missing fields from type, failed to parse")]
-    InvalidParse,
+    #[error("{entity}: missing field {field}")]
+    MissingField {
+        entity: EntityContext,
+        field: String,
+    },
+    #[error("{entity}: field {field} should be {expected} but is {actual}")]
+    WrongType {
+        entity: EntityContext,
+        field: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("{entity}: field {field} has unknown value {value}, expected one of {}", .expected.join(", "))]
+    UnknownEnumValue {
+        entity: EntityContext,
+        field: String,
+        value: String,
+        expected: Vec<String>,
+    },
+    #[error("{entity}: field {field} is out of range, {value} is not {expected}")]
+    OutOfRange {
+        entity: EntityContext,
+        field: String,
+        value: String,
+        expected: String,
+    },
+    #[error("{entity}: field {field} {reason}")]
+    InvalidField {
+        entity: EntityContext,
+        field: String,
+        reason: String,
+    },
 }
+
+impl IndexingError {
+    pub fn new(entity: EntityContext, error: FieldError) -> Self {
+        let field = error.field;
+        match error.kind {
+            FieldErrorKind::Missing => IndexingError::MissingField { entity, field },
+            FieldErrorKind::WrongType { expected, actual } => IndexingError::WrongType {
+                entity,
+                field,
+                expected,
+                actual,
+            },
+            FieldErrorKind::UnknownEnumValue { value, expected } => {
+                IndexingError::UnknownEnumValue {
+                    entity,
+                    field,
+                    value,
+                    expected,
+                }
+            }
+            FieldErrorKind::OutOfRange { value, expected } => IndexingError::OutOfRange {
+                entity,
+                field,
+                value,
+                expected,
+            },
+            FieldErrorKind::Invalid(reason) => IndexingError::InvalidField {
+                entity,
+                field,
+                reason,
+            },
+        }
+    }
+}
+
 pub struct EntityIndexed;
 
-// TODO Convert to serde...
-pub trait ParseFields {
-    fn parse(fields: &HashMap<String, Option<Value>>) -> anyhow::Result<Self>
-    where
-        Self: Sized;
+/// Entity classes built from LDtk fields, deserialized with serde by default, see `from_fields`
+pub trait ParseFields: DeserializeOwned {
+    fn parse(fields: &HashMap<String, Option<Value>>) -> Result<Self, FieldError> {
+        from_fields(fields)
+    }
 }
 
-fn insert<'a>(
-    eid: Entity,
-    name: &'a String,
-    s: Result<impl Component, Error>,
-    mut c: Commands<'a>,
-) -> Commands<'a> {
-    if let Ok(component) = s {
-        c.entity(eid).insert(component).insert(EntityIndexed);
-    } else {
-        error!("Failed to parse entity {} into a known type", name);
-        c.entity(eid).insert(EntityIndexed);
+/// Parses a map entity and inserts whatever components it needs
+pub type IndexFn =
+    Box<dyn Fn(&mut Commands, Entity, &MapEntity) -> Result<(), FieldError> + Send + Sync>;
+
+/// Entity classes by LDtk identifier, filled in by plugins through `register_entity_class`
+#[derive(Default)]
+pub struct EntityClassRegistry {
+    classes: HashMap<String, IndexFn>,
+}
+
+impl EntityClassRegistry {
+    /// Registers a class that parses straight into a single component
+    pub fn register<T: ParseFields + Component>(&mut self, identifier: &str) {
+        self.register_with(identifier, |c, eid, entity| {
+            c.entity(eid).insert(T::parse(&entity.fields)?);
+            Ok(())
+        });
+    }
+
+    pub fn register_with(
+        &mut self,
+        identifier: &str,
+        index: impl Fn(&mut Commands, Entity, &MapEntity) -> Result<(), FieldError>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        if self
+            .classes
+            .insert(identifier.to_string(), Box::new(index))
+            .is_some()
+        {
+            warn!("Entity class {} was registered twice", identifier);
+        }
+    }
+
+    pub fn get(&self, identifier: &str) -> Option<&IndexFn> {
+        self.classes.get(identifier)
+    }
+}
+
+pub trait RegisterEntityClass {
+    fn register_entity_class<T: ParseFields + Component>(&mut self, identifier: &str) -> &mut Self;
+}
+
+impl RegisterEntityClass for AppBuilder {
+    fn register_entity_class<T: ParseFields + Component>(&mut self, identifier: &str) -> &mut Self {
+        self.world_mut()
+            .get_resource_or_insert_with(EntityClassRegistry::default)
+            .register::<T>(identifier);
+        self
     }
-    c
 }
 
 pub fn index_entities(
-    mut c_orig: Commands,
+    mut c: Commands,
+    registry: Res<EntityClassRegistry>,
     entities: Query<(Entity, &MapEntity), Without<EntityIndexed>>,
+    mut errors: EventWriter<MapLoadError>,
 ) {
-    let mut c = c_orig;
     for (eid, entity) in entities.iter() {
-        info!("Indexing entity {} {:?}", entity.name, eid);
-        let fields = &entity.fields;
-        let name = &entity.name;
-        c = match entity.name.as_str() {
-            START_LOCATION_ID => insert(eid, name, StartLocation::parse(fields), c),
-            DOOR_ID => insert(eid, name, Door::parse(fields), c),
-            PATROL_PATH_ID => insert(eid, name, PatrolPath::parse(fields), c),
-            GOLD_ID => insert(eid, name, Gold::parse(fields), c),
-            _ => {
-                warn!("Unknown entity type {}", entity.name);
+        info!(
+            "Indexing entity {} at {} {:?}",
+            entity.name, entity.grid_pos, eid
+        );
+        c.entity(eid).insert(EntityIndexed);
 
-                // Err(anyhow!("Unknown entity type {}", entity.name))
-                insert(eid, name, Ok(EntityIndexed), c)
+        let index = match registry.get(&entity.name) {
+            Some(index) => index,
+            None => {
+                warn!("Unknown entity type {}", entity.name);
+                continue;
             }
         };
-
-        // if let Ok(component) = component_o {
-        //     c.entity(eid).insert(component).insert(EntityIndexed);
-        // } else {
-        //     error!("Failed to parse entity {} into a known type", entity.name);
-        //     c.entity(eid).insert(EntityIndexed);
-        // }
+        if let Err(e) = index(&mut c, eid, entity) {
+            report_load_error(
+                &mut errors,
+                MapLoadError::InvalidEntity(Box::new(IndexingError::new(entity.into(), e))),
+            );
+        }
     }
 }
@@ -0,0 +1,56 @@
+use crate::entity_class::gold::{picked_up, pickup_sensor};
+use crate::entity_class::indexer::ParseFields;
+use crate::map::map_loader::MapEntity;
+use crate::tags::Player;
+use bevy::prelude::*;
+use heron::CollisionEvent;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+pub const KEY_ID: &str = "Key";
+
+/// A key lying in the level, opens locked doors whose `Key` field matches its name
+#[derive(Deserialize)]
+pub struct Key {
+    #[serde(rename = "Name")]
+    pub name: String,
+}
+
+impl ParseFields for Key {}
+
+/// Names of the keys the player has picked up
+#[derive(Default, Debug)]
+pub struct Keys(HashSet<String>);
+
+impl Keys {
+    pub fn add(&mut self, name: String) {
+        self.0.insert(name);
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+}
+
+pub fn add_key_sensors(mut c: Commands, keys: Query<(Entity, &MapEntity), Added<Key>>) {
+    for (eid, entity) in keys.iter() {
+        c.entity(eid)
+            .insert_bundle(pickup_sensor((entity.size / 2.).extend(0.)));
+    }
+}
+
+pub fn collect_keys(
+    mut c: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    keys: Query<&Key>,
+    mut player: Query<&mut Keys, With<Player>>,
+) {
+    for key_id in collisions.iter().filter_map(picked_up) {
+        if let Ok(key) = keys.get(key_id) {
+            for mut held in player.iter_mut() {
+                held.add(key.name.clone());
+            }
+            c.entity(key_id).despawn();
+        }
+    }
+}
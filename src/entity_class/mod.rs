@@ -1,45 +1,95 @@
+use crate::entity_class::door::{Door, DOOR_ID};
+use crate::entity_class::gold::{Gold, GoldCollected, GOLD_ID};
 use crate::entity_class::health::Damaged;
+use crate::entity_class::indexer::{EntityClassRegistry, RegisterEntityClass};
+use crate::entity_class::key::{Key, KEY_ID};
+use crate::entity_class::patrol_path::{PatrolPath, PATROL_PATH_ID};
+use crate::entity_class::start_location::{StartLocation, START_LOCATION_ID};
+use crate::entity_class::vision::Spotted;
+use crate::entity_class::wave::{WaveDirector, WaveStarted};
+use crate::ticks::run_game_tick;
+use crate::ui::Score;
 use crate::GameStage;
-use bevy::core::FixedTimestep;
 use bevy::prelude::*;
 
-mod creature;
-mod door;
-mod enemy;
-mod gold;
-mod health;
-mod indexer;
-mod lifetime;
-mod movement;
-mod patrol_path;
-mod player;
-mod projectile;
-mod start_location;
+pub mod attack;
+pub mod creature;
+pub mod door;
+pub mod enemy;
+pub mod fields;
+pub mod gold;
+pub mod health;
+pub mod indexer;
+pub mod key;
+pub mod lifetime;
+pub mod movement;
+pub mod path_follower;
+pub mod patrol_path;
+pub mod player;
+pub mod projectile;
+pub mod start_location;
+pub mod vision;
+pub mod wave;
 
 pub struct EntityClasses;
 
 impl Plugin for EntityClasses {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system(indexer::index_entities.system())
-            .add_system(start_location::spawn_from_spawn_location.system())
+        app.init_resource::<EntityClassRegistry>()
+            .init_resource::<Score>()
+            .init_resource::<WaveDirector>()
+            .add_event::<WaveStarted>()
+            .register_entity_class::<StartLocation>(START_LOCATION_ID)
+            .register_entity_class::<Door>(DOOR_ID)
+            .register_entity_class::<PatrolPath>(PATROL_PATH_ID)
+            .register_entity_class::<Gold>(GOLD_ID)
+            .register_entity_class::<Key>(KEY_ID)
+            .add_startup_system(player::spawn_camera.system())
+            .add_system(indexer::index_entities.system())
+            .add_system(wave::reset_waves_on_level_change.system())
+            .add_system(wave::direct_waves.system().label(wave::WaveLabel))
+            .add_system(
+                start_location::spawn_from_spawn_location
+                    .system()
+                    .after(wave::WaveLabel),
+            )
             .add_system(start_location::mark_spawns_as_invisible.system())
+            .add_system(patrol_path::place_patrol_paths.system())
+            .add_system(enemy::find_nearest_patrol_path.system())
             .add_stage(GameStage, SystemStage::parallel())
             .add_system_to_stage(GameStage, player::player_movement.system())
             .add_system_to_stage(GameStage, player::move_camera_with_player.system())
             .add_system_to_stage(GameStage, movement::animate_creature.system())
             .add_system_set(
                 SystemSet::new()
-                    .with_run_criteria(FixedTimestep::step(0.05))
+                    .with_run_criteria(run_game_tick.system())
+                    .with_system(
+                        attack::acquire_targets
+                            .system()
+                            .label(enemy::EnemyFunctions::Target),
+                    )
                     .with_system(
                         enemy::rand_update_enemy_state
                             .system()
-                            .label(enemy::EnemyFunctions::ChangeState),
+                            .label(enemy::EnemyFunctions::ChangeState)
+                            .after(enemy::EnemyFunctions::Target),
                     )
                     .with_system(
-                        enemy::move_down
+                        enemy::move_enemies
                             .system()
                             .label(enemy::EnemyFunctions::Move)
                             .after(enemy::EnemyFunctions::ChangeState),
+                    )
+                    .with_system(
+                        path_follower::plan_paths
+                            .system()
+                            .label(enemy::EnemyFunctions::PlanPath)
+                            .after(enemy::EnemyFunctions::Move),
+                    )
+                    .with_system(
+                        path_follower::follow_paths
+                            .system()
+                            .after(enemy::EnemyFunctions::PlanPath),
                     ),
             )
             .add_system(movement::update_last_direction.system())
@@ -47,9 +97,21 @@ impl Plugin for EntityClasses {
             .add_system(health::on_damage_drain_health.system())
             .add_system(health::despawn_if_zero_health.system())
             .add_event::<Damaged>()
+            .add_event::<GoldCollected>()
+            .add_system(gold::add_gold_sensors.system())
+            .add_system(gold::collect_gold.system())
+            .add_system(key::add_key_sensors.system())
+            .add_system(key::collect_keys.system())
+            .add_system(door::add_door_colliders.system())
+            .add_system(door::player_opens_doors.system())
+            .add_system(door::enemies_open_doors.system())
+            .add_system_to_stage(CoreStage::PostUpdate, door::update_doors.system())
             .add_system(projectile::on_collide_apply_damage.system())
             .add_system(projectile::on_collide_despawn.system())
             .add_system(projectile::cast_projectile.system())
+            .add_system(attack::enemy_attack.system())
+            .add_event::<Spotted>()
+            .add_system(vision::look_for_targets.system())
             .add_system(lifetime::apply_lifetime.system());
     }
 }
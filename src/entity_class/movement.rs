@@ -10,8 +10,21 @@ pub enum MovementDirection {
     Right,
 }
 
+impl MovementDirection {
+    /// Unit vector pointing in this direction
+    pub fn vector(self) -> Vec2 {
+        match self {
+            MovementDirection::Up => Vec2::Y,
+            MovementDirection::Down => -Vec2::Y,
+            MovementDirection::Left => -Vec2::X,
+            MovementDirection::Right => Vec2::X,
+        }
+    }
+}
+
 pub struct LastMovementDirection(pub MovementDirection);
 
+#[allow(clippy::type_complexity)]
 pub fn animate_creature(
     mut q: Query<
         (&LastMovementDirection, &mut TextureAtlasSprite),
@@ -0,0 +1,112 @@
+use crate::map::pathfinding::{find_path, Diagonals, PathOptions};
+use crate::map::tile_grid::TileGrid;
+use bevy::prelude::*;
+use heron::Velocity;
+
+/// How close a follower has to get to a waypoint before heading to the next one
+pub const WAYPOINT_REACHED: f32 = 8.;
+
+/// Walks its entity to a goal around the walls of the level, by pushing its velocity along
+/// a path planned over the tile grid
+pub struct PathFollower {
+    /// How far the follower reaches out from its centre, it won't squeeze through anything narrower
+    pub radius: f32,
+    pub diagonals: Diagonals,
+    /// Velocity added every step towards the next waypoint
+    pub acceleration: f32,
+    goal: Option<Vec2>,
+    /// The goal cell the current waypoints lead to
+    planned_for: Option<IVec2>,
+    waypoints: Vec<Vec2>,
+}
+
+impl PathFollower {
+    pub fn new(radius: f32) -> Self {
+        PathFollower {
+            radius,
+            diagonals: Diagonals::NoCornerCutting,
+            acceleration: 10.,
+            goal: None,
+            planned_for: None,
+            waypoints: vec![],
+        }
+    }
+
+    /// Whether the goal was planned for and no path leads to it
+    pub fn is_blocked(&self) -> bool {
+        self.planned_for.is_some() && self.waypoints.is_empty()
+    }
+
+    /// Heads for `goal`, the path is only planned again once the goal moves to another cell
+    pub fn go_to(&mut self, goal: Vec2) {
+        self.goal = Some(goal);
+    }
+
+    pub fn stop(&mut self) {
+        self.goal = None;
+        self.planned_for = None;
+        self.waypoints.clear();
+    }
+}
+
+/// Plans a path for every follower whose goal moved to a new cell, and for all of them
+/// whenever the grid changes, e.g. when a door opens
+pub fn plan_paths(grid: Option<Res<TileGrid>>, mut q: Query<(&mut PathFollower, &Transform)>) {
+    let grid = match grid {
+        Some(grid) => grid,
+        None => return,
+    };
+
+    for (mut follower, transform) in q.iter_mut() {
+        let goal = match follower.goal {
+            Some(goal) => goal.extend(transform.translation.z),
+            None => continue,
+        };
+        let goal_cell = grid.coords.world_to_grid(goal);
+        if follower.planned_for == Some(goal_cell) && !grid.is_changed() {
+            continue;
+        }
+
+        let options = PathOptions {
+            diagonals: follower.diagonals,
+            ..Default::default()
+        }
+        .with_radius(follower.radius, grid.coords.tile_size().x);
+        // Walking straight at a goal that can't be reached only pins the follower against a
+        // wall, it waits for the goal or the grid to change instead
+        follower.waypoints =
+            find_path(&grid, transform.translation, goal, options).unwrap_or_default();
+        follower.planned_for = Some(goal_cell);
+    }
+}
+
+/// Pushes followers towards their next waypoint, the last one being the goal itself.
+/// Followers keep heading for the goal until they are stopped, or stand still while no
+/// path leads to it
+pub fn follow_paths(mut q: Query<(&mut PathFollower, &Transform, &mut Velocity)>) {
+    for (mut follower, transform, mut vel) in q.iter_mut() {
+        let goal = match follower.goal {
+            Some(goal) => goal,
+            None => continue,
+        };
+
+        let position = transform.translation.truncate();
+        while follower.waypoints.len() > 1
+            && follower.waypoints[0].distance(position) < WAYPOINT_REACHED
+        {
+            follower.waypoints.remove(0);
+        }
+
+        // The last waypoint is where the goal was when planning, it may have moved since.
+        // Without a planned path, e.g. when there is no grid, head straight for the goal
+        let next = match follower.waypoints.as_slice() {
+            [] if follower.is_blocked() => continue,
+            [next, _, ..] => *next,
+            _ => goal,
+        };
+        let to_next = next - position;
+        if to_next.length() > f32::EPSILON {
+            vel.linear += (to_next.normalize() * follower.acceleration).extend(0.);
+        }
+    }
+}
@@ -1,46 +1,153 @@
-use crate::entity_class::indexer::{IndexingError, ParseFields};
+use crate::entity_class::fields::GridPoint;
+use crate::entity_class::indexer::ParseFields;
+use crate::map::map_loader::MapEntity;
 use bevy::prelude::*;
 use bevy_inspector_egui::Inspectable;
-use serde_json::Value;
-use std::collections::HashMap;
+use serde::Deserialize;
 
 pub const PATROL_PATH_ID: &str = "Patrol_Path";
 
+#[derive(Inspectable, Deserialize, Debug)]
+pub struct PatrolPath {
+    /// Lets start locations pick this path by name instead of the nearest one
+    #[serde(rename = "Name", default)]
+    #[inspectable(ignore)]
+    pub name: Option<String>,
+    #[serde(rename = "Path")]
+    #[inspectable(ignore)]
+    path: Vec<GridPoint>,
+    /// The path in world space, filled in once the level's coordinates are known
+    #[serde(skip)]
+    #[inspectable(ignore)]
+    pub waypoints: Vec<Vec2>,
+}
+
+impl ParseFields for PatrolPath {}
+
+/// What a follower does once it reaches the last waypoint
+#[derive(Inspectable, Deserialize, Copy, Clone, Debug, PartialEq, Default)]
+pub enum PatrolMode {
+    /// Head back to the first waypoint and go round again
+    #[default]
+    Loop,
+    /// Walk the path backwards, then forwards again
+    PingPong,
+    /// Stop at the last waypoint
+    OneShot,
+}
+
 #[derive(Inspectable, Debug)]
-pub struct PatrolPath;
-
-impl ParseFields for PatrolPath {
-    fn parse(fields: &HashMap<String, Option<Value>>) -> anyhow::Result<Self>
-    where
-        Self: Sized,
-    {
-        let path = fields.get("Path").ok_or(IndexingError::InvalidParse)?;
-        let path_arr: Vec<Vec2> = match path {
-            Some(Value::Array(arr)) => arr
-                .iter()
-                .map(|node| {
-                    let pos: anyhow::Result<Vec2> = match node {
-                        Value::Object(node_map) => {
-                            let x = match node_map.get("cx").ok_or(IndexingError::InvalidParse) {
-                                Ok(Value::Number(n)) => n.as_f64().unwrap(),
-                                _ => Err(IndexingError::InvalidParse)?,
-                            };
-                            let y = match node_map.get("cy").ok_or(IndexingError::InvalidParse) {
-                                Ok(Value::Number(n)) => n.as_f64().unwrap(),
-                                _ => Err(IndexingError::InvalidParse)?,
-                            };
-
-                            Ok(Vec2::new(x as f32, y as f32))
-                        }
-                        _ => Err(IndexingError::InvalidParse)?,
-                    };
-                    pos
-                })
-                .map(|x| x.unwrap())
-                .collect(),
-            _ => Err(IndexingError::InvalidParse)?,
+pub struct PatrolFollower {
+    /// Name of the path to follow, the nearest path is used when there is none
+    #[inspectable(ignore)]
+    pub link: Option<String>,
+    pub mode: PatrolMode,
+    #[inspectable(ignore)]
+    pub path: Option<Entity>,
+    pub waypoint: usize,
+    /// 1 when walking the path forwards, -1 when walking it backwards
+    pub direction: i32,
+    pub finished: bool,
+}
+
+impl PatrolFollower {
+    pub fn new(link: Option<String>, mode: PatrolMode) -> Self {
+        PatrolFollower {
+            link,
+            mode,
+            path: None,
+            waypoint: 0,
+            direction: 1,
+            finished: false,
+        }
+    }
+
+    /// Moves on to the next waypoint of a path with `len` waypoints
+    pub fn advance(&mut self, len: usize) {
+        match next_waypoint(self.mode, self.waypoint, self.direction, len) {
+            Some((waypoint, direction)) => {
+                self.waypoint = waypoint;
+                self.direction = direction;
+            }
+            None => self.finished = true,
+        }
+    }
+}
+
+/// The waypoint and direction after `waypoint`, or `None` when a one shot patrol is over
+pub fn next_waypoint(
+    mode: PatrolMode,
+    waypoint: usize,
+    direction: i32,
+    len: usize,
+) -> Option<(usize, i32)> {
+    if len < 2 {
+        return match mode {
+            PatrolMode::OneShot => None,
+            _ => Some((0, direction)),
         };
+    }
+
+    let next = waypoint as i32 + direction;
+    if next >= 0 && next < len as i32 {
+        return Some((next as usize, direction));
+    }
+    match mode {
+        PatrolMode::Loop => Some((0, 1)),
+        PatrolMode::PingPong => Some(((waypoint as i32 - direction) as usize, -direction)),
+        PatrolMode::OneShot => None,
+    }
+}
+
+/// Converts newly indexed paths from grid cells of their layer into world positions
+pub fn place_patrol_paths(mut paths: Query<(&mut PatrolPath, &MapEntity)>) {
+    for (mut path, entity) in paths.iter_mut() {
+        if path.waypoints.len() == path.path.len() {
+            continue;
+        }
+        path.waypoints = path
+            .path
+            .iter()
+            .map(|point| entity.coords.grid_to_world((*point).into(), 0.).truncate())
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walk(mode: PatrolMode, len: usize, steps: usize) -> Vec<usize> {
+        let mut follower = PatrolFollower::new(None, mode);
+        let mut visited = vec![follower.waypoint];
+        for _ in 0..steps {
+            follower.advance(len);
+            if follower.finished {
+                break;
+            }
+            visited.push(follower.waypoint);
+        }
+        visited
+    }
+
+    #[test]
+    fn loop_goes_back_to_the_start() {
+        assert_eq!(walk(PatrolMode::Loop, 3, 6), vec![0, 1, 2, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn ping_pong_turns_around_at_both_ends() {
+        assert_eq!(walk(PatrolMode::PingPong, 3, 6), vec![0, 1, 2, 1, 0, 1, 2]);
+    }
+
+    #[test]
+    fn one_shot_stops_at_the_end() {
+        assert_eq!(walk(PatrolMode::OneShot, 3, 6), vec![0, 1, 2]);
+    }
 
-        Ok(PatrolPath {})
+    #[test]
+    fn single_waypoint_paths_stay_put() {
+        assert_eq!(walk(PatrolMode::PingPong, 1, 3), vec![0, 0, 0, 0]);
+        assert_eq!(walk(PatrolMode::OneShot, 1, 3), vec![0]);
     }
 }
@@ -1,26 +1,30 @@
 use crate::entity_class::creature::Creature;
+use crate::entity_class::key::Keys;
 use crate::entity_class::movement::{LastMovementDirection, MovementDirection};
+use crate::input::Action;
+use crate::map::map_loader::LevelScoped;
 use crate::tags::{MainCamera, Player};
 use crate::GameLayer;
 use bevy::prelude::*;
 use heron::{CollisionLayers, CollisionShape, RigidBody, RotationConstraints, Velocity};
 
+/// The camera outlives level changes, so levels without a player start still have one
+pub fn spawn_camera(mut c: Commands) {
+    c.spawn()
+        .insert_bundle(OrthographicCameraBundle::new_2d())
+        .insert(MainCamera);
+}
+
 pub fn spawn_player(
-    mut c: &mut Commands,
+    c: &mut Commands,
     assets: &Res<AssetServer>,
     texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
     transform: &Transform,
-) {
+) -> Entity {
     let player_spritesheet = assets.load("player.spritemap.png");
     let texture_atlas = TextureAtlas::from_grid(player_spritesheet, Vec2::new(64., 64.), 4, 1);
     let texture_atlas_handle = texture_atlases.add(texture_atlas);
 
-    // Spawn camera
-    c.spawn()
-        .insert_bundle(OrthographicCameraBundle::new_2d())
-        .insert(MainCamera)
-        .id();
-
     c.spawn()
         .insert_bundle(SpriteSheetBundle {
             texture_atlas: texture_atlas_handle,
@@ -29,6 +33,7 @@ pub fn spawn_player(
         })
         .insert(Player)
         .insert(Creature)
+        .insert(LevelScoped)
         .insert(Velocity::from_linear(Vec3::default()))
         .insert(RigidBody::Dynamic)
         .insert(RotationConstraints::lock())
@@ -39,30 +44,37 @@ pub fn spawn_player(
         .insert(
             CollisionLayers::none()
                 .with_group(GameLayer::Player)
-                .with_masks(&[GameLayer::World, GameLayer::Enemy]),
+                .with_masks(&[
+                    GameLayer::World,
+                    GameLayer::Enemy,
+                    GameLayer::Pickup,
+                    GameLayer::Projectile,
+                ]),
         )
-        .insert(LastMovementDirection(MovementDirection::Down));
+        .insert(LastMovementDirection(MovementDirection::Down))
+        .insert(Keys::default())
+        .id()
 }
 
-pub fn player_movement(input: Res<Input<KeyCode>>, mut q: Query<&mut Velocity, With<Player>>) {
+pub fn player_movement(input: Res<Input<Action>>, mut q: Query<&mut Velocity, With<Player>>) {
     let move_speed = 10.;
     let min_speed = 0.01;
     let max_speed = 100.;
     let friction = 0.95;
     for mut real_vel in q.iter_mut() {
-        let mut vel = real_vel.clone();
+        let mut vel = *real_vel;
 
         // Adjust current velocity
-        if input.pressed(KeyCode::W) {
+        if input.pressed(Action::MoveUp) {
             vel.linear.y += move_speed
         };
-        if input.pressed(KeyCode::A) {
+        if input.pressed(Action::MoveLeft) {
             vel.linear.x -= move_speed
         };
-        if input.pressed(KeyCode::S) {
+        if input.pressed(Action::MoveDown) {
             vel.linear.y -= move_speed
         };
-        if input.pressed(KeyCode::D) {
+        if input.pressed(Action::MoveRight) {
             vel.linear.x += move_speed
         };
 
@@ -85,6 +97,7 @@ pub fn player_movement(input: Res<Input<KeyCode>>, mut q: Query<&mut Velocity, W
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn move_camera_with_player(
     mut q: QuerySet<(
         Query<&mut Transform, With<MainCamera>>,
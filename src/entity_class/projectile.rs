@@ -1,13 +1,15 @@
 use crate::entity_class::health::Damaged;
 use crate::entity_class::lifetime::Lifetime;
-use crate::tags::{MainCamera, Player};
+use crate::entity_class::movement::LastMovementDirection;
+use crate::input::{Action, CursorPosition, GamepadAim};
+use crate::map::map_loader::LevelScoped;
+use crate::rng::GameRng;
+use crate::tags::Player;
 use crate::GameLayer;
 use bevy::prelude::*;
-use bevy_egui::egui::{Color32, Shape, Stroke};
-use bevy_prototype_debug_lines::DebugLines;
 use heron::{
     CollisionEvent, CollisionLayers, CollisionShape, PhysicMaterial, RigidBody,
-    RotationConstraints, Velocity,
+    RotationConstraints, SensorShape, Velocity,
 };
 use rand::Rng;
 
@@ -16,23 +18,15 @@ pub struct Projectile {
     pub damage: i32,
 }
 
-#[derive(Bundle)]
+// The derive forgets the bundle once its components are moved out
+#[allow(clippy::forget_non_drop)]
+#[derive(Bundle, Default)]
 pub struct ProjectileBundle {
     pub projectile: Projectile,
     pub collision_layers: CollisionLayers,
     pub velocity: Velocity,
 }
 
-impl Default for ProjectileBundle {
-    fn default() -> Self {
-        ProjectileBundle {
-            projectile: Default::default(),
-            collision_layers: Default::default(),
-            velocity: Default::default(),
-        }
-    }
-}
-
 impl Default for Projectile {
     fn default() -> Self {
         Projectile {
@@ -75,7 +69,12 @@ pub fn on_collide_apply_damage(
         });
 }
 
-pub fn on_collide_despawn(mut c: Commands, mut collisions: EventReader<CollisionEvent>) {
+// Open doors are sensors on the World layer, projectiles fly through them
+pub fn on_collide_despawn(
+    mut c: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    sensors: Query<(), With<SensorShape>>,
+) {
     collisions
         .iter()
         .filter(|x| x.is_started())
@@ -91,6 +90,7 @@ pub fn on_collide_despawn(mut c: Commands, mut collisions: EventReader<Collision
                 None
             }
         })
+        .filter(|(world_id, _)| sensors.get(*world_id).is_err())
         .for_each(|(_, projectile_id)| {
             c.entity(projectile_id).despawn();
         });
@@ -123,75 +123,98 @@ fn is_world(layers: CollisionLayers) -> bool {
     //     && layers.contains_group(GameLayer::World)
 }
 
-// TODO generalize to any CASTER instead of player
-// TODO abstract over input mode
+/// The sprite sheet every projectile is drawn from
+pub fn projectile_atlas(
+    asset_server: &AssetServer,
+    texture_atlases: &mut Assets<TextureAtlas>,
+) -> Handle<TextureAtlas> {
+    let projectile_spritesheet = asset_server.load("projectile.spritemap.png");
+    let texture_atlas = TextureAtlas::from_grid(projectile_spritesheet, Vec2::new(64., 64.), 4, 1);
+    texture_atlases.add(texture_atlas)
+}
+
+/// Fires a projectile from `from`, it can only hit things in the `masks` layers
+pub fn spawn_projectile(
+    c: &mut Commands,
+    texture_atlas: Handle<TextureAtlas>,
+    from: Vec3,
+    velocity: Vec3,
+    projectile: Projectile,
+    masks: &[GameLayer],
+    lifetime: f32,
+) -> Entity {
+    c.spawn()
+        .insert_bundle(SpriteSheetBundle {
+            texture_atlas,
+            transform: Transform::from_xyz(from.x, from.y, 100.),
+            ..Default::default()
+        })
+        .insert_bundle(ProjectileBundle {
+            projectile,
+            collision_layers: CollisionLayers::none()
+                .with_group(GameLayer::Projectile)
+                .with_masks(masks),
+            velocity: Velocity::from_linear(velocity),
+        })
+        .insert(LevelScoped)
+        .insert(Lifetime {
+            lifetime: Timer::from_seconds(lifetime, false),
+        })
+        .insert(RigidBody::Dynamic)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(6., 6., 0.),
+            border_radius: None,
+        })
+        .insert(RotationConstraints::lock())
+        .insert(PhysicMaterial {
+            restitution: 0.0,
+            density: 1.0,
+            friction: 0.0,
+        })
+        .id()
+}
+
+/// How fast projectiles aimed with a stick or the facing direction fly
+const AIM_SPEED: f32 = 200.;
+
 // Create projectile
+#[allow(clippy::too_many_arguments)]
 pub fn cast_projectile(
     mut c: Commands,
-    windows: Res<Windows>,
-    input: Res<Input<MouseButton>>,
-    player: Query<&Transform, With<Player>>,
-    camera: Query<&Transform, With<MainCamera>>,
+    input: Res<Input<Action>>,
+    cursor: Res<CursorPosition>,
+    aim: Res<GamepadAim>,
+    player: Query<(&Transform, &LastMovementDirection), With<Player>>,
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut rng: ResMut<GameRng>,
 ) {
-    // On click, fire a projectile from the player with a velocity relative to the distance the cursor is from the player
+    // On fire, shoot a projectile from the player with a velocity relative to the distance the cursor is from the player
     // projectile should have limited bounces and limited lifetime
     // Spread?
-    let window = windows.get_primary().unwrap();
-    let mut rng = rand::thread_rng();
-    let projectile_spritesheet = asset_server.load("projectile.spritemap.png");
-    let texture_atlas = TextureAtlas::from_grid(projectile_spritesheet, Vec2::new(64., 64.), 4, 1);
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
-
-    if input.just_pressed(MouseButton::Left) {
-        if let Ok(start) = player.single() {
-            if let Some(cursor) = window.cursor_position() {
-                let size = Vec2::new(window.width() as f32, window.height() as f32);
-
-                // the default orthographic projection is in pixels from the center;
-                // just undo the translation
-                let p = cursor - size / 2.0;
-
-                // assuming there is exactly one main camera entity, so this is OK
-                let camera_transform = camera.single().unwrap();
-
-                // apply the camera transform
-                let pos_wld = camera_transform.compute_matrix() * p.extend(0.0).extend(1.0);
-                let vel = (start.translation.truncate() - pos_wld.truncate().truncate()) * 1.;
-
-                c.spawn()
-                    .insert_bundle(SpriteSheetBundle {
-                        texture_atlas: texture_atlas_handle,
-                        transform: Transform::from_xyz(
-                            start.translation.x,
-                            start.translation.y,
-                            100.,
-                        ),
-                        ..Default::default()
-                    })
-                    .insert_bundle(ProjectileBundle {
-                        projectile: Projectile { size, damage: 50 },
-                        collision_layers: CollisionLayers::none()
-                            .with_group(GameLayer::Projectile)
-                            .with_masks(&[GameLayer::World, GameLayer::Enemy, GameLayer::Player]),
-                        velocity: Velocity::from_linear(vel.extend(0.) * Vec3::new(-1., -1., 1.)),
-                    })
-                    .insert(Lifetime {
-                        lifetime: Timer::from_seconds(rng.gen_range(1.0..2.0), false),
-                    })
-                    .insert(RigidBody::Dynamic)
-                    .insert(CollisionShape::Cuboid {
-                        half_extends: Vec3::new(6., 6., 0.),
-                        border_radius: None,
-                    })
-                    .insert(RotationConstraints::lock())
-                    .insert(PhysicMaterial {
-                        restitution: 0.0,
-                        density: 1.0,
-                        friction: 0.0,
-                    });
-            }
-        }
+    if !input.just_pressed(Action::Fire) {
+        return;
+    }
+    if let Ok((start, facing)) = player.single() {
+        // A tilted stick wins over the cursor, controllers without either shoot straight ahead
+        let vel = match (aim.0, cursor.0) {
+            (Some(stick), _) => stick * AIM_SPEED,
+            (None, Some(target)) => target - start.translation.truncate(),
+            (None, None) => facing.0.vector() * AIM_SPEED,
+        };
+
+        spawn_projectile(
+            &mut c,
+            projectile_atlas(&asset_server, &mut texture_atlases),
+            start.translation,
+            vel.extend(0.),
+            Projectile {
+                damage: 50,
+                ..Default::default()
+            },
+            // Not the player, who would be hit by their own shots as they leave
+            &[GameLayer::World, GameLayer::Enemy],
+            rng.stream("projectile_lifetime").gen_range(1.0..2.0),
+        );
     }
 }
@@ -1,12 +1,18 @@
 use crate::entity_class::enemy::spawn_enemy;
-use crate::entity_class::indexer::{IndexingError, ParseFields};
+use crate::entity_class::indexer::ParseFields;
+use crate::entity_class::patrol_path::{PatrolFollower, PatrolMode};
 use crate::entity_class::player::spawn_player;
+use crate::entity_class::wave::WaveDirector;
+use crate::map::utils::MapCoords;
+use crate::rng::GameRng;
 use crate::tags::Player;
-use anyhow::anyhow;
+use crate::ticks::GameTime;
 use bevy::prelude::*;
-use serde_json::Value;
-use std::collections::HashMap;
+use rand::Rng;
+use serde::Deserialize;
+use std::f32::consts::TAU;
 
+#[derive(Deserialize, PartialEq, Debug)]
 enum StartEntity {
     Player,
     Enemy,
@@ -14,60 +20,133 @@ enum StartEntity {
 
 pub const START_LOCATION_ID: &str = "Start_Location";
 
+#[derive(Deserialize)]
 pub struct StartLocation {
+    #[serde(rename = "Character")]
     character: StartEntity,
-    count: i64,
-    spawned: i64,
+    #[serde(rename = "Count")]
+    count: u32,
+    /// Name of the patrol path spawned enemies follow, the nearest one if unset
+    #[serde(rename = "Patrol", default)]
+    patrol: Option<String>,
+    #[serde(rename = "Patrol_Mode", default)]
+    patrol_mode: PatrolMode,
+    /// Seconds between two spawns, one creature spawns every frame if unset
+    #[serde(rename = "Interval", default)]
+    interval: f32,
+    /// Replace enemies that died, keeping `count` of them alive
+    #[serde(rename = "Respawn", default)]
+    respawn: bool,
+    /// Wave the spawner waits for, it starts with the level if unset
+    #[serde(rename = "Wave", default)]
+    pub wave: Option<u32>,
+    /// Radius in tiles around the location that creatures are scattered over
+    #[serde(rename = "Spread", default)]
+    spread: f32,
+    #[serde(skip)]
+    spawned: u32,
+    #[serde(skip)]
+    cooldown: f32,
 }
 
-impl ParseFields for StartLocation {
-    fn parse(fields: &HashMap<String, Option<Value>>) -> anyhow::Result<Self> {
-        let character = fields.get("Character").ok_or(IndexingError::InvalidParse)?;
-        let character_enum = match character {
-            Some(Value::String(s)) => StartEntity::from_string(s.as_str()),
-            _ => Err(IndexingError::InvalidParse)?,
-        }?;
-
-        let count = fields.get("Count").ok_or(IndexingError::InvalidParse)?;
-        let count_value = match count {
-            Some(Value::Number(n)) => n.as_i64().unwrap(),
-            _ => Err(IndexingError::InvalidParse)?,
+impl ParseFields for StartLocation {}
+
+impl StartLocation {
+    pub fn is_active(&self, wave: u32) -> bool {
+        self.wave.is_none_or(|start| wave >= start)
+    }
+
+    /// Whether the spawner is done and all of its creatures are gone.
+    /// Player and respawning spawners are never done, so they never hold a wave back
+    pub fn is_cleared(&self, alive: u32) -> bool {
+        if self.character == StartEntity::Player || self.respawns() {
+            return true;
+        }
+        self.spawned >= self.count && alive == 0
+    }
+
+    // Players have their own camera and death handling, only enemies come back
+    fn respawns(&self) -> bool {
+        self.respawn && self.character == StartEntity::Enemy
+    }
+
+    /// Counts down the interval, true if a creature should spawn this frame
+    fn tick(&mut self, delta: f32, alive: u32) -> bool {
+        let wanted = if self.respawns() {
+            alive < self.count
+        } else {
+            self.spawned < self.count
         };
+        if !wanted {
+            return false;
+        }
 
-        Ok(StartLocation {
-            character: character_enum,
-            count: count_value,
-            spawned: 0,
-        })
+        self.cooldown -= delta;
+        if self.cooldown > 0. {
+            return false;
+        }
+        self.cooldown = self.interval;
+        self.spawned += 1;
+        true
     }
 }
 
-impl StartEntity {
-    pub fn from_string(s: &str) -> anyhow::Result<Self> {
-        match s {
-            "Player" => Ok(StartEntity::Player),
-            "Enemy" => Ok(StartEntity::Enemy),
-            _ => Err(anyhow!("No StartEntity binding for {}", s)),
-        }
+/// Links a creature to the start location that spawned it
+pub struct SpawnedBy(pub Entity);
+
+/// Creatures spawned by each start location that are still alive
+pub fn count_alive(spawned: &Query<&SpawnedBy>, location: Entity) -> u32 {
+    spawned.iter().filter(|by| by.0 == location).count() as u32
+}
+
+fn spread_offset(rng: &mut impl Rng, radius: f32) -> Vec3 {
+    if radius <= 0. {
+        return Vec3::ZERO;
     }
+    // sqrt keeps the points evenly spread over the disc instead of bunching in the middle
+    let distance = radius * rng.gen::<f32>().sqrt();
+    let angle = rng.gen_range(0.0..TAU);
+    Vec3::new(angle.cos(), angle.sin(), 0.) * distance
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_from_spawn_location(
     mut c: Commands,
-    mut q: Query<(&mut StartLocation, &Transform)>,
+    time: Res<GameTime>,
+    director: Res<WaveDirector>,
+    coords: Option<Res<MapCoords>>,
+    mut q: Query<(Entity, &mut StartLocation, &Transform)>,
+    spawned: Query<&SpawnedBy>,
     assets: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut rng: ResMut<GameRng>,
 ) {
-    for (mut start, location) in q.iter_mut() {
-        if start.spawned < start.count {
-            match start.character {
-                StartEntity::Player => {
-                    spawn_player(&mut c, &assets, &mut texture_atlases, location)
-                }
-                StartEntity::Enemy => spawn_enemy(&mut c, &assets, &mut texture_atlases, location),
-            }
-            start.spawned += 1;
+    let tile_size = coords.map_or(0., |coords| coords.tile_size().x);
+
+    for (eid, mut start, location) in q.iter_mut() {
+        if !start.is_active(director.wave) {
+            continue;
+        }
+        if !start.tick(time.delta_seconds(), count_alive(&spawned, eid)) {
+            continue;
         }
+
+        let mut transform = *location;
+        transform.translation +=
+            spread_offset(rng.stream("spawn_spread"), start.spread * tile_size);
+        let creature = match start.character {
+            StartEntity::Player => spawn_player(&mut c, &assets, &mut texture_atlases, &transform),
+            StartEntity::Enemy => spawn_enemy(
+                &mut c,
+                &assets,
+                &mut texture_atlases,
+                &transform,
+                PatrolFollower::new(start.patrol.clone(), start.patrol_mode),
+            ),
+        };
+        c.entity(creature)
+            .insert(SpawnedBy(eid))
+            .insert(rng.fork("creatures"));
     }
 }
 
@@ -77,3 +156,60 @@ pub fn mark_spawns_as_invisible(mut q: Query<&mut Visible, With<StartLocation>>)
         n.is_visible = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enemies(count: u32, interval: f32, respawn: bool) -> StartLocation {
+        StartLocation {
+            character: StartEntity::Enemy,
+            count,
+            patrol: None,
+            patrol_mode: PatrolMode::Loop,
+            interval,
+            respawn,
+            wave: None,
+            spread: 0.,
+            spawned: 0,
+            cooldown: 0.,
+        }
+    }
+
+    #[test]
+    fn spawns_count_creatures_once() {
+        let mut start = enemies(2, 0., false);
+        assert!(start.tick(0.1, 0));
+        assert!(start.tick(0.1, 1));
+        assert!(!start.tick(0.1, 0));
+        assert!(start.is_cleared(0));
+        assert!(!start.is_cleared(1));
+    }
+
+    #[test]
+    fn waits_for_the_interval() {
+        let mut start = enemies(3, 1., false);
+        assert!(start.tick(0.5, 0));
+        assert!(!start.tick(0.5, 1));
+        assert!(start.tick(0.5, 1));
+    }
+
+    #[test]
+    fn respawns_keep_count_alive() {
+        let mut start = enemies(1, 0., true);
+        assert!(start.tick(0.1, 0));
+        assert!(!start.tick(0.1, 1));
+        assert!(start.tick(0.1, 0));
+        // Waves would never advance without an interval if they waited on it
+        assert!(start.is_cleared(1));
+    }
+
+    #[test]
+    fn waits_for_its_wave() {
+        let mut start = enemies(1, 0., false);
+        start.wave = Some(2);
+        assert!(!start.is_active(1));
+        assert!(start.is_active(2));
+        assert!(start.is_active(3));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::entity_class::movement::LastMovementDirection;
+use crate::tags::Player;
+use crate::GameLayer;
+use bevy::prelude::*;
+use heron::rapier_plugin::PhysicsWorld;
+use heron::{CollisionLayers, SensorShape};
+
+/// What an observer can see, a cone facing the way it last moved
+pub struct VisionCone {
+    /// Full width of the cone in radians
+    pub angle: f32,
+    pub range: f32,
+    /// Targets currently in view
+    pub seen: Vec<Entity>,
+}
+
+impl Default for VisionCone {
+    fn default() -> Self {
+        VisionCone {
+            angle: std::f32::consts::FRAC_PI_2,
+            range: 200.,
+            seen: vec![],
+        }
+    }
+}
+
+impl VisionCone {
+    /// Whether `offset` from the observer is inside the cone, ignoring walls
+    pub fn covers(&self, facing: Vec2, offset: Vec2) -> bool {
+        let distance = offset.length();
+        if distance > self.range {
+            return false;
+        }
+        // Targets standing on top of the observer can't be missed
+        distance == 0. || facing.angle_between(offset).abs() <= self.angle / 2.
+    }
+}
+
+/// Sent when a target comes into an observer's view
+pub struct Spotted {
+    pub observer: Entity,
+    pub target: Entity,
+}
+
+/// Checks every vision cone against the players, raycasting against the world so walls and
+/// closed doors block the view
+pub fn look_for_targets(
+    physics_world: PhysicsWorld,
+    mut spotted: EventWriter<Spotted>,
+    mut observers: Query<(Entity, &mut VisionCone, &Transform, &LastMovementDirection)>,
+    targets: Query<(Entity, &Transform), With<Player>>,
+    sensors: Query<(), With<SensorShape>>,
+) {
+    // The ray only interacts with walls, open doors are sensors and don't count
+    let ray_layers = CollisionLayers::none()
+        .with_group(GameLayer::Enemy)
+        .with_mask(GameLayer::World);
+
+    for (observer, mut cone, transform, direction) in observers.iter_mut() {
+        let start = transform.translation;
+        let facing = direction.0.vector();
+
+        let seen: Vec<Entity> = targets
+            .iter()
+            .filter(|(_, target)| {
+                let ray = (target.translation - start).truncate();
+                cone.covers(facing, ray)
+                    && physics_world
+                        .ray_cast_with_filter(start, ray.extend(0.), true, ray_layers, |eid| {
+                            sensors.get(eid).is_err()
+                        })
+                        .is_none()
+            })
+            .map(|(eid, _)| eid)
+            .collect();
+
+        for target in seen.iter().filter(|target| !cone.seen.contains(target)) {
+            spotted.send(Spotted {
+                observer,
+                target: *target,
+            });
+        }
+        if cone.seen != seen {
+            cone.seen = seen;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cones_cover_what_is_in_front_and_in_range() {
+        let cone = VisionCone::default();
+        let facing = Vec2::Y;
+        assert!(cone.covers(facing, Vec2::new(0., 100.)));
+        assert!(cone.covers(facing, Vec2::new(50., 100.)));
+        assert!(cone.covers(facing, Vec2::ZERO));
+        assert!(!cone.covers(facing, Vec2::new(0., 300.)));
+        assert!(!cone.covers(facing, Vec2::new(100., 10.)));
+        assert!(!cone.covers(facing, Vec2::new(0., -100.)));
+    }
+}
@@ -0,0 +1,78 @@
+use crate::entity_class::start_location::{count_alive, SpawnedBy, StartLocation};
+use crate::map::map_loader::ChangeLevel;
+use crate::ticks::GameTime;
+use bevy::prelude::*;
+
+/// Decides which wave is running. Start locations with a `Wave` field stay quiet until it begins
+pub struct WaveDirector {
+    pub wave: u32,
+    /// Seconds before the next wave starts on its own, waves only start when cleared if unset
+    pub interval: Option<f32>,
+    /// Start the next wave as soon as every creature of the current one is dead
+    pub advance_when_cleared: bool,
+    elapsed: f32,
+}
+
+impl Default for WaveDirector {
+    fn default() -> Self {
+        WaveDirector {
+            wave: 0,
+            interval: None,
+            advance_when_cleared: true,
+            elapsed: 0.,
+        }
+    }
+}
+
+impl WaveDirector {
+    pub fn next_wave(&mut self) {
+        self.wave += 1;
+        self.elapsed = 0.;
+        info!("Starting wave {}", self.wave);
+    }
+}
+
+#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct WaveLabel;
+
+/// Sent when a new wave starts
+pub struct WaveStarted(pub u32);
+
+pub fn direct_waves(
+    time: Res<GameTime>,
+    mut director: ResMut<WaveDirector>,
+    mut started: EventWriter<WaveStarted>,
+    locations: Query<(Entity, &StartLocation)>,
+    spawned: Query<&SpawnedBy>,
+) {
+    // Nothing left to start once the last wave is running
+    let last_wave = locations.iter().filter_map(|(_, start)| start.wave).max();
+    if last_wave.is_none_or(|last| director.wave >= last) {
+        return;
+    }
+
+    director.elapsed += time.delta_seconds();
+    let timed_out = director
+        .interval
+        .is_some_and(|interval| director.elapsed >= interval);
+    let cleared = director.advance_when_cleared
+        && locations
+            .iter()
+            .filter(|(_, start)| start.is_active(director.wave))
+            .all(|(eid, start)| start.is_cleared(count_alive(&spawned, eid)));
+
+    if timed_out || cleared {
+        director.next_wave();
+        started.send(WaveStarted(director.wave));
+    }
+}
+
+pub fn reset_waves_on_level_change(
+    mut events: EventReader<ChangeLevel>,
+    mut director: ResMut<WaveDirector>,
+) {
+    if events.iter().next().is_some() {
+        director.wave = 0;
+        director.elapsed = 0.;
+    }
+}
@@ -0,0 +1,208 @@
+use crate::input::CursorPosition;
+use crate::map::map_loader::MapLocation;
+use crate::map::tile_grid::TileGrid;
+use crate::replay::{Playback, Recorder, Replay};
+use crate::rng::GameRng;
+use crate::tags::Player;
+use crate::ticks::GameTicks;
+use crate::GamePlugin;
+use bevy::asset::AssetPlugin;
+use bevy::ecs::component::Component;
+use bevy::input::gamepad::{Gamepad, GamepadEventRaw, GamepadEventType};
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::mouse::MouseButtonInput;
+use bevy::input::{ElementState, InputPlugin};
+use bevy::prelude::*;
+use bevy::transform::TransformPlugin;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the map to load before giving up
+const LOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The parts of `DefaultPlugins` the game can't do without, minus the window and renderer.
+/// Textures are never loaded, but the asset collections the map loader fills have to exist
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugin(TransformPlugin)
+            .add_plugin(InputPlugin)
+            .add_plugin(AssetPlugin)
+            .add_asset::<Texture>()
+            .add_asset::<ColorMaterial>()
+            .add_asset::<TextureAtlas>()
+            .init_resource::<Windows>();
+    }
+}
+
+/// The game running under `MinimalPlugins`, every frame lasting exactly one gameplay tick
+pub struct Simulation {
+    pub app: App,
+}
+
+impl Simulation {
+    pub fn new(map: &str, seed: u64) -> Self {
+        let mut builder = App::build();
+        builder
+            .add_plugins(MinimalPlugins)
+            .add_plugin(HeadlessPlugin)
+            .insert_resource(MapLocation(map.to_string()))
+            .insert_resource(GameRng::new(seed))
+            .insert_resource(GameTicks::manual())
+            .add_plugin(GamePlugin);
+        Simulation { app: builder.app }
+    }
+
+    /// Starts the game on `map`, returning once the first level has spawned its player
+    pub fn load(map: &str, seed: u64) -> Self {
+        let mut simulation = Simulation::new(map, seed);
+        simulation.wait_for_level();
+        simulation
+    }
+
+    /// Like `load`, recording every frame from the first one the level is in place.
+    /// The replay is kept in memory, see `recording`
+    pub fn record(map: &str, seed: u64) -> Self {
+        let mut simulation = Simulation::new(map, seed);
+        let replay = Replay::new(seed, map.to_string());
+        // Only ever written on `AppExit`, which headless runs don't send
+        simulation
+            .app
+            .world
+            .insert_resource(Recorder::new(PathBuf::new(), replay));
+        simulation.wait_for_level();
+        simulation
+    }
+
+    /// Starts the game the replay was recorded on, with its frames ready to play
+    pub fn replay(replay: Replay) -> Self {
+        let mut simulation = Simulation::new(&replay.map, replay.seed);
+        simulation.app.world.insert_resource(Playback::new(replay));
+        simulation.wait_for_level();
+        simulation
+    }
+
+    fn wait_for_level(&mut self) {
+        let started = Instant::now();
+        while !self.level_loaded() {
+            if started.elapsed() > LOAD_TIMEOUT {
+                let map = self.app.world.get_resource::<MapLocation>().unwrap();
+                panic!("{} did not load within {:?}", map.0, LOAD_TIMEOUT);
+            }
+            self.app.update();
+            // The project is read on the IO task pool
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn level_loaded(&mut self) -> bool {
+        self.app.world.get_resource::<TileGrid>().is_some() && self.player().is_some()
+    }
+
+    /// Runs `ticks` frames, each one running the gameplay systems once
+    pub fn step(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.app.update();
+        }
+    }
+
+    /// Steps until every frame of the replay being played has run
+    pub fn play_to_end(&mut self) {
+        while !self
+            .app
+            .world
+            .get_resource::<Playback>()
+            .expect("not playing a replay")
+            .finished()
+        {
+            self.app.update();
+        }
+    }
+
+    /// The frames recorded so far by a simulation started with `record`
+    pub fn recording(&self) -> &Replay {
+        &self
+            .app
+            .world
+            .get_resource::<Recorder>()
+            .expect("not recording")
+            .replay
+    }
+
+    pub fn world(&mut self) -> &mut World {
+        &mut self.app.world
+    }
+
+    pub fn send<E: Component>(&mut self, event: E) {
+        self.app
+            .world
+            .get_resource_mut::<Events<E>>()
+            .expect("event was never added to the app")
+            .send(event);
+    }
+
+    pub fn press_key(&mut self, key: KeyCode) {
+        self.key(key, ElementState::Pressed);
+    }
+
+    pub fn release_key(&mut self, key: KeyCode) {
+        self.key(key, ElementState::Released);
+    }
+
+    fn key(&mut self, key: KeyCode, state: ElementState) {
+        self.send(KeyboardInput {
+            scan_code: 0,
+            key_code: Some(key),
+            state,
+        });
+    }
+
+    pub fn press_mouse(&mut self, button: MouseButton) {
+        self.send(MouseButtonInput {
+            button,
+            state: ElementState::Pressed,
+        });
+    }
+
+    pub fn release_mouse(&mut self, button: MouseButton) {
+        self.send(MouseButtonInput {
+            button,
+            state: ElementState::Released,
+        });
+    }
+
+    pub fn connect_gamepad(&mut self, gamepad: Gamepad) {
+        self.send(GamepadEventRaw(gamepad, GamepadEventType::Connected));
+    }
+
+    pub fn move_gamepad_axis(&mut self, gamepad: Gamepad, axis: GamepadAxisType, value: f32) {
+        self.send(GamepadEventRaw(
+            gamepad,
+            GamepadEventType::AxisChanged(axis, value),
+        ));
+    }
+
+    /// Points the cursor at a spot in the world, there being no window to move it in
+    pub fn set_cursor(&mut self, position: Option<Vec2>) {
+        self.app
+            .world
+            .get_resource_mut::<CursorPosition>()
+            .unwrap()
+            .0 = position;
+    }
+
+    /// Every entity with a `T`
+    pub fn entities<T: Component>(&mut self) -> Vec<Entity> {
+        let mut query = self.app.world.query_filtered::<Entity, With<T>>();
+        query.iter(&self.app.world).collect()
+    }
+
+    pub fn count<T: Component>(&mut self) -> usize {
+        self.entities::<T>().len()
+    }
+
+    pub fn player(&mut self) -> Option<Entity> {
+        self.entities::<Player>().first().copied()
+    }
+}
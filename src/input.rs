@@ -0,0 +1,372 @@
+use crate::tags::MainCamera;
+use bevy::input::gamepad::{Gamepad, GamepadAxis, GamepadButton, GamepadEvent, GamepadEventType};
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Where the bindings are read from, relative to the working directory
+pub const BINDINGS_PATH: &str = "assets/bindings.json";
+
+/// Where the cursor points in the world, None while it is outside the window.
+/// Gameplay reads this rather than the window, so replays and headless runs can aim too
+#[derive(Default)]
+pub struct CursorPosition(pub Option<Vec2>);
+
+#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct TrackCursorLabel;
+
+#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct TrackGamepadsLabel;
+
+/// Updates `Input<Action>` from the devices, before any gameplay system reads it
+#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct ActionsLabel;
+
+/// What the player wants to do, gameplay reads these through `Input<Action>` instead of
+/// asking for keys, so every device and binding works the same
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Fire,
+    Interact,
+    /// Leaves the game, until there is a pause menu to open
+    Pause,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+/// A device input that holds an action down
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    GamepadButton(GamepadButtonType),
+    /// Held while the axis is pushed past the dead zone in `AxisDirection`
+    GamepadAxis(GamepadAxisType, AxisDirection),
+}
+
+/// The two axes of a stick
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Stick {
+    pub x: GamepadAxisType,
+    pub y: GamepadAxisType,
+}
+
+/// Which inputs trigger each action, loaded from `BINDINGS_PATH`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InputBindings {
+    pub actions: HashMap<Action, Vec<Binding>>,
+    /// The stick that aims `Fire` on gamepads
+    pub aim: Option<Stick>,
+    /// How far sticks and triggers have to be pushed before they count
+    pub dead_zone: f32,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use AxisDirection::*;
+
+        let actions = vec![
+            (
+                Action::MoveUp,
+                vec![
+                    Binding::Key(KeyCode::W),
+                    Binding::GamepadButton(GamepadButtonType::DPadUp),
+                    Binding::GamepadAxis(GamepadAxisType::LeftStickY, Positive),
+                ],
+            ),
+            (
+                Action::MoveDown,
+                vec![
+                    Binding::Key(KeyCode::S),
+                    Binding::GamepadButton(GamepadButtonType::DPadDown),
+                    Binding::GamepadAxis(GamepadAxisType::LeftStickY, Negative),
+                ],
+            ),
+            (
+                Action::MoveLeft,
+                vec![
+                    Binding::Key(KeyCode::A),
+                    Binding::GamepadButton(GamepadButtonType::DPadLeft),
+                    Binding::GamepadAxis(GamepadAxisType::LeftStickX, Negative),
+                ],
+            ),
+            (
+                Action::MoveRight,
+                vec![
+                    Binding::Key(KeyCode::D),
+                    Binding::GamepadButton(GamepadButtonType::DPadRight),
+                    Binding::GamepadAxis(GamepadAxisType::LeftStickX, Positive),
+                ],
+            ),
+            (
+                Action::Fire,
+                vec![
+                    Binding::Mouse(MouseButton::Left),
+                    Binding::GamepadButton(GamepadButtonType::RightTrigger2),
+                ],
+            ),
+            (
+                Action::Interact,
+                vec![
+                    Binding::Key(KeyCode::E),
+                    Binding::GamepadButton(GamepadButtonType::South),
+                ],
+            ),
+            (
+                Action::Pause,
+                vec![
+                    Binding::Key(KeyCode::Escape),
+                    Binding::GamepadButton(GamepadButtonType::Start),
+                ],
+            ),
+        ];
+
+        InputBindings {
+            actions: actions.into_iter().collect(),
+            aim: Some(Stick {
+                x: GamepadAxisType::RightStickX,
+                y: GamepadAxisType::RightStickY,
+            }),
+            dead_zone: 0.25,
+        }
+    }
+}
+
+impl InputBindings {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// The bindings in `BINDINGS_PATH`, the defaults when it can't be read
+    pub fn load_or_default() -> Self {
+        InputBindings::load(BINDINGS_PATH.as_ref()).unwrap_or_else(|err| {
+            warn!(
+                "Using the default bindings, could not load {}: {}",
+                BINDINGS_PATH, err
+            );
+            InputBindings::default()
+        })
+    }
+}
+
+/// Swaps the default bindings for the ones in `BINDINGS_PATH`
+pub fn load_bindings(mut bindings: ResMut<InputBindings>) {
+    *bindings = InputBindings::load_or_default();
+}
+
+/// Which way the aiming stick points, None while it is centred
+#[derive(Default)]
+pub struct GamepadAim(pub Option<Vec2>);
+
+/// The gamepads plugged in, in the order they were connected
+#[derive(Default)]
+pub struct Gamepads(pub Vec<Gamepad>);
+
+/// The device state bindings are checked against
+struct Devices<'a> {
+    keys: &'a Input<KeyCode>,
+    mouse: &'a Input<MouseButton>,
+    buttons: &'a Input<GamepadButton>,
+    axes: &'a Axis<GamepadAxis>,
+    gamepads: &'a [Gamepad],
+    dead_zone: f32,
+}
+
+impl Devices<'_> {
+    /// Whether the binding holds its action this frame, taps that started and ended within
+    /// the frame included, so they still trigger it once
+    fn held(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.keys.pressed(key) || self.keys.just_pressed(key),
+            Binding::Mouse(button) => self.mouse.pressed(button) || self.mouse.just_pressed(button),
+            Binding::GamepadButton(button) => self.gamepads.iter().any(|gamepad| {
+                let button = GamepadButton(*gamepad, button);
+                self.buttons.pressed(button) || self.buttons.just_pressed(button)
+            }),
+            Binding::GamepadAxis(axis, direction) => self.gamepads.iter().any(|gamepad| {
+                let value = self.axes.get(GamepadAxis(*gamepad, axis)).unwrap_or(0.);
+                match direction {
+                    AxisDirection::Positive => value > self.dead_zone,
+                    AxisDirection::Negative => value < -self.dead_zone,
+                }
+            }),
+        }
+    }
+
+    fn stick(&self, stick: Stick) -> Option<Vec2> {
+        self.gamepads
+            .iter()
+            .map(|gamepad| {
+                let axis = |axis| self.axes.get(GamepadAxis(*gamepad, axis)).unwrap_or(0.);
+                Vec2::new(axis(stick.x), axis(stick.y))
+            })
+            .find(|direction| direction.length() > self.dead_zone)
+    }
+}
+
+pub struct GameInputPlugin;
+
+impl Plugin for GameInputPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<CursorPosition>()
+            .init_resource::<InputBindings>()
+            .init_resource::<Input<Action>>()
+            .init_resource::<GamepadAim>()
+            .init_resource::<Gamepads>()
+            .add_system_to_stage(
+                CoreStage::First,
+                track_cursor.system().label(TrackCursorLabel),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                track_gamepads
+                    .system()
+                    .label(TrackGamepadsLabel)
+                    .after(InputSystem)
+                    .before(ActionsLabel),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                update_actions
+                    .system()
+                    .label(ActionsLabel)
+                    .after(InputSystem),
+            );
+    }
+}
+
+pub fn track_gamepads(mut events: EventReader<GamepadEvent>, mut gamepads: ResMut<Gamepads>) {
+    for GamepadEvent(gamepad, event) in events.iter() {
+        match event {
+            GamepadEventType::Connected => {
+                info!("{:?} connected", gamepad);
+                gamepads.0.push(*gamepad);
+            }
+            GamepadEventType::Disconnected => {
+                info!("{:?} disconnected", gamepad);
+                gamepads.0.retain(|connected| connected != gamepad);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Presses and releases actions as their bindings are, on the devices replays play back too
+#[allow(clippy::too_many_arguments)]
+pub fn update_actions(
+    bindings: Res<InputBindings>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    gamepads: Res<Gamepads>,
+    mut actions: ResMut<Input<Action>>,
+    mut aim: ResMut<GamepadAim>,
+) {
+    let devices = Devices {
+        keys: &keys,
+        mouse: &mouse,
+        buttons: &buttons,
+        axes: &axes,
+        gamepads: &gamepads.0,
+        dead_zone: bindings.dead_zone,
+    };
+    actions.update();
+    for (action, action_bindings) in bindings.actions.iter() {
+        let held = action_bindings.iter().any(|binding| devices.held(*binding));
+        if held && !actions.pressed(*action) {
+            actions.press(*action);
+        } else if !held && actions.pressed(*action) {
+            actions.release(*action);
+        }
+    }
+    aim.0 = bindings.aim.and_then(|stick| devices.stick(stick));
+}
+
+/// Follows the cursor of the primary window, when there is one
+pub fn track_cursor(
+    windows: Res<Windows>,
+    camera: Query<&Transform, With<MainCamera>>,
+    mut cursor: ResMut<CursorPosition>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    cursor.0 = window
+        .cursor_position()
+        .zip(camera.single().ok())
+        .map(|(position, camera)| {
+            let size = Vec2::new(window.width(), window.height());
+
+            // the default orthographic projection is in pixels from the center;
+            // just undo the translation
+            let p = position - size / 2.0;
+
+            // apply the camera transform
+            (camera.compute_matrix() * p.extend(0.0).extend(1.0))
+                .truncate()
+                .truncate()
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_bindings_are_the_defaults() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(BINDINGS_PATH);
+        assert_eq!(
+            InputBindings::load(&path).unwrap(),
+            InputBindings::default()
+        );
+    }
+
+    #[test]
+    fn bindings_hold_actions() {
+        let mut keys = Input::default();
+        keys.press(KeyCode::W);
+        let mut axes = Axis::default();
+        let gamepad = Gamepad(0);
+        axes.set(GamepadAxis(gamepad, GamepadAxisType::LeftStickX), -0.5);
+        axes.set(GamepadAxis(gamepad, GamepadAxisType::RightStickX), 0.1);
+        axes.set(GamepadAxis(gamepad, GamepadAxisType::RightStickY), 0.1);
+        let devices = Devices {
+            keys: &keys,
+            mouse: &Input::default(),
+            buttons: &Input::default(),
+            axes: &axes,
+            gamepads: &[gamepad],
+            dead_zone: 0.25,
+        };
+
+        assert!(devices.held(Binding::Key(KeyCode::W)));
+        assert!(!devices.held(Binding::Key(KeyCode::S)));
+        assert!(devices.held(Binding::GamepadAxis(
+            GamepadAxisType::LeftStickX,
+            AxisDirection::Negative
+        )));
+        assert!(!devices.held(Binding::GamepadAxis(
+            GamepadAxisType::LeftStickX,
+            AxisDirection::Positive
+        )));
+        // Resting sticks drift a little
+        let aim = Stick {
+            x: GamepadAxisType::RightStickX,
+            y: GamepadAxisType::RightStickY,
+        };
+        assert_eq!(devices.stick(aim), None);
+    }
+}
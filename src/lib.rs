@@ -0,0 +1,57 @@
+pub mod entity_class;
+pub mod headless;
+pub mod input;
+pub mod map;
+pub mod replay;
+pub mod rng;
+pub mod tags;
+pub mod ticks;
+pub mod ui;
+
+use crate::entity_class::EntityClasses;
+use crate::input::GameInputPlugin;
+use crate::map::map_colliders::ColliderLayers;
+use crate::map::map_loader::{MapPlugin, MapScale};
+use crate::replay::ReplayPlugin;
+use crate::rng::GameRng;
+use crate::ticks::GameTimePlugin;
+use bevy::prelude::*;
+use heron::prelude::*;
+
+#[derive(PhysicsLayer)]
+pub enum GameLayer {
+    World,
+    Player,
+    Enemy,
+    Projectile,
+    Pickup,
+}
+
+#[derive(StageLabel, Debug, Eq, Hash, PartialEq, Clone)]
+pub struct GameStage;
+
+/// The map, physics and entity classes, everything the game needs apart from a window.
+/// Resources inserted before adding the plugin win over its defaults
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let world = app.world_mut();
+        world.get_resource_or_insert_with(|| Gravity::from(Vec3::new(0.0, 0.0, 0.0)));
+        // One world unit per LDtk pixel, so 64px tiles line up with the 64px creature sprites.
+        // Positions ignored the scale before `MapCoords`, this keeps levels laid out as they were
+        world.get_resource_or_insert_with(|| MapScale(1.));
+        // Background walls are decoration only
+        world.get_resource_or_insert_with(|| {
+            ColliderLayers::default().with_layer("Background", false)
+        });
+
+        app.init_resource::<GameRng>()
+            .add_plugin(GameTimePlugin)
+            .add_plugin(GameInputPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(MapPlugin)
+            .add_plugin(PhysicsPlugin::default())
+            .add_plugin(EntityClasses);
+    }
+}
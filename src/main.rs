@@ -1,68 +1,105 @@
-#[allow(unused, illegal_floating_point_literal_pattern, unused_variables)]
-mod entity_class;
-mod map;
-pub mod tags;
-mod ui;
-
-use crate::entity_class::EntityClasses;
-use crate::map::map_loader::{MapLocation, MapPlugin, MapScale};
-use crate::ui::GameOverlayPlugin;
 use bevy::app::AppExit;
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContext, EguiPlugin};
 use bevy_inspector_egui::WorldInspectorPlugin;
 use bevy_prototype_debug_lines::DebugLinesPlugin;
-use heron::prelude::*;
+use rusty_jam_08_21::input::{load_bindings, Action};
+use rusty_jam_08_21::map::ldtk_asset::LdtkProject;
+use rusty_jam_08_21::map::map_loader::{ChangeLevel, LevelSelector, Map, MapLocation, StartLevel};
+use rusty_jam_08_21::map::tile_grid::TileGrid;
+use rusty_jam_08_21::replay::{Playback, Recorder, Replay};
+use rusty_jam_08_21::rng::GameRng;
+use rusty_jam_08_21::tags::Player;
+use rusty_jam_08_21::ui::GameOverlayPlugin;
+use rusty_jam_08_21::GamePlugin;
 
 // use bevy_retrograde::prelude::*;
 
 const SCREEN_HEIGHT: f32 = 256.;
 const SCREEN_WIDTH: f32 = 256.;
 
-#[derive(PhysicsLayer)]
-pub enum GameLayer {
-    World,
-    Player,
-    Enemy,
-    Projectile,
-}
+fn main() {
+    // `--replay <file>` plays a recorded session back, with the seed, map and level it was on
+    let playback = cli_arg("--replay").map(|path| {
+        Replay::load(path.as_ref())
+            .unwrap_or_else(|err| panic!("Could not load replay {}: {}", path, err))
+    });
+    let (seed, map, level) = match &playback {
+        Some(replay) => (replay.seed, replay.map.clone(), replay.level.clone()),
+        // Lets designers jump straight into a level, e.g. `--level Level_1`
+        None => (seed_from_cli(), "map.ldtk".to_string(), cli_arg("--level")),
+    };
 
-#[derive(StageLabel, Debug, Eq, Hash, PartialEq, Clone)]
-struct GameStage;
+    let mut app = App::build();
+    app.insert_resource(WindowDescriptor {
+        width: SCREEN_WIDTH * 4.,
+        height: SCREEN_HEIGHT * 4.,
+        title: "Illusion Of Security".to_string(),
+        ..Default::default()
+    })
+    .insert_resource(MapLocation(map.clone()))
+    .insert_resource(StartLevel(
+        level
+            .clone()
+            .map(LevelSelector::Identifier)
+            .unwrap_or(LevelSelector::Index(0)),
+    ))
+    // Runs can be repeated with `--seed <number>`, the seed used is logged on startup
+    .insert_resource(GameRng::new(seed));
 
-fn main() {
-    App::build()
-        .insert_resource(WindowDescriptor {
-            width: SCREEN_WIDTH * 4.,
-            height: SCREEN_HEIGHT * 4.,
-            title: "Illusion Of Security".to_string(),
-            ..Default::default()
-        })
-        .insert_resource(Gravity::from(Vec3::new(0.0, 0.0, 0.0)))
-        .insert_resource(MapLocation("assets/map.ldtk".into()))
-        .insert_resource(MapScale(0.25))
-        .add_plugins(DefaultPlugins)
-        .add_plugin(MapPlugin)
-        .add_plugin(PhysicsPlugin::default())
+    if let Some(replay) = playback {
+        app.insert_resource(Playback::new(replay));
+    }
+    // `--record <file>` saves the session when the game is quit, for `--replay`
+    if let Some(path) = cli_arg("--record") {
+        let replay = Replay {
+            level,
+            ..Replay::new(seed, map)
+        };
+        app.insert_resource(Recorder::new(path.into(), replay));
+    }
+
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(GamePlugin)
         .add_plugin(EguiPlugin)
         .add_plugin(WorldInspectorPlugin::new())
-        .add_plugin(EntityClasses)
         .add_plugin(DebugLinesPlugin)
         .add_plugin(GameOverlayPlugin)
         .add_startup_system(setup.system())
+        // Rebind controls in `assets/bindings.json`
+        .add_startup_system(load_bindings.system())
         .add_system(quit_system.system())
         .add_system(ui.system())
         .run()
 }
 
-fn setup(asset_server: Res<AssetServer>) {
+/// Finds the value following `name` in the command line arguments
+fn cli_arg(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next()?;
+    args.next()
+}
+
+/// The `--seed` argument, or a random seed when there is none
+fn seed_from_cli() -> u64 {
+    cli_arg("--seed")
+        .map(|seed| seed.parse().expect("--seed should be a whole number"))
+        .unwrap_or_else(rand::random)
+}
+
+fn setup(asset_server: Res<AssetServer>, rng: Res<GameRng>) {
     asset_server.watch_for_changes().unwrap();
+    info!("Random seed: {}", rng.seed());
 }
 
 fn ui(
     ui_context: Res<EguiContext>,
     windows: Res<Windows>,
-    // q_player: Query<&Transform, With<Player>>,
+    map: Option<Res<Map>>,
+    projects: Res<Assets<LdtkProject>>,
+    mut change_level: EventWriter<ChangeLevel>,
+    tile_grid: Option<Res<TileGrid>>,
+    q_player: Query<&Transform, With<Player>>,
 ) {
     let window = windows.get_primary().unwrap();
     let mut cursor_pos = window.cursor_position().unwrap_or_default();
@@ -71,247 +108,39 @@ fn ui(
         ui.label("Pos:");
         ui.add(egui::Slider::new(&mut cursor_pos.x, 0.0..=window.width()).text("x"));
         ui.add(egui::Slider::new(&mut cursor_pos.y, 0.0..=window.height()).text("y"));
-        // ui.add(egui::Label::new(format!(
-        //     "player x {}",
-        //     player.translation.x
-        // )));
-        // ui.add(egui::Label::new(format!(
-        //     "player y {}",
-        //     player.translation.y
-        // )));
+        if let (Some(grid), Some(player)) = (&tile_grid, q_player.iter().next()) {
+            let cell = grid.coords.world_to_grid(player.translation);
+            ui.label(format!(
+                "Player cell: {}, {} ({:?})",
+                cell.x,
+                cell.y,
+                grid.world_type(cell)
+            ));
+        }
     });
-}
 
-// fn player_vision_cone(
-//     mut c: Commands,
-//     physics_world: PhysicsWorld,
-//     player_q: Query<(Entity, &Transform, &GlobalTransform), With<Player>>,
-// ) {
-//     let samples = 10;
-//     let cone_radius = 150.;
-//     let slice = 2. * PI / samples as f32;
-//
-//     // Spawn a point to the left and right of the player
-//     // let left_debug = c
-//     //     .spawn()
-//     //     .insert_bundle(ShapeBundle {
-//     //         shape: Shape::Circle {
-//     //             center: Default::default(),
-//     //             radius: 10.,
-//     //             fill: Color32::TRANSPARENT,
-//     //             stroke: Stroke::new(5., Color32::YELLOW),
-//     //         },
-//     //         transform: Transform::from_xyz(-cone_radius, 0., 5.),
-//     //         // global_transform: GlobalTransform::from_translation(
-//     //         //     local.translation + Vec3::new(-100., 0., 0.),
-//     //         // ),
-//     //         ..Default::default()
-//     //     })
-//     //     .id();
-//     // let right_debug = c
-//     //     .spawn()
-//     //     .insert_bundle(ShapeBundle {
-//     //         shape: Shape::Circle {
-//     //             center: Default::default(),
-//     //             radius: 10.,
-//     //             fill: Color32::TRANSPARENT,
-//     //             stroke: Stroke::new(5., Color32::YELLOW),
-//     //         },
-//     //         transform: Transform::from_xyz(cone_radius, 0., 5.),
-//     //         // global_transform: GlobalTransform::from_translation(
-//     //         //     local.translation + Vec3::new(-100., 0., 0.),
-//     //         // ),
-//     //         ..Default::default()
-//     //     })
-//     //     .id();
-//     //
-//     // let ray_circle = c
-//     //     .spawn()
-//     //     .insert_bundle(ShapeBundle {
-//     //         shape: Shape::Circle {
-//     //             center: Default::default(),
-//     //             radius: cone_radius,
-//     //             fill: Color32::TRANSPARENT,
-//     //             stroke: Stroke::new(5., Color32::BLUE),
-//     //         },
-//     //         transform: Transform::from_xyz(0., 0., 5.),
-//     //         // global_transform: GlobalTransform::from_translation(
-//     //         //     local.translation + Vec3::new(-100., 0., 0.),
-//     //         // ),
-//     //         ..Default::default()
-//     //     })
-//     //     .id();
-//
-//     if let Ok((e_id, local, global)) = player_q.single() {
-//         // For sample in a circle around the player
-//
-//         // let mut loop_debug_lines = vec![];
-//
-//         for i in 0..samples {
-//             let deg = i as f32 * slice;
-//             let pos_y = deg.sin() * -cone_radius;
-//             let pos_x = deg.cos() * cone_radius;
-//
-//             trace!(
-//                 "Ray casting at {} -> {} {}",
-//                 global.translation,
-//                 pos_x,
-//                 pos_y
-//             );
-//
-//             let hit = physics_world.ray_cast_with_filter(
-//                 global.translation,
-//                 global.translation + Transform::from_xyz(pos_x, pos_y, 0.0).translation
-//                     - local.translation,
-//                 true,
-//                 CollisionLayers::none()
-//                     .with_group(GameLayer::World)
-//                     .with_mask(GameLayer::World),
-//                 |x| true,
-//             );
-//
-//             if let Some(info) = hit {
-//                 trace!(
-//                     "Got a hit with distance {} ",
-//                     info.collision_point.distance(global.translation)
-//                 );
-//
-//                 // // Draw the collision
-//                 // c.spawn()
-//                 //     .insert_bundle(ShapeBundle {
-//                 //         shape: Shape::Circle {
-//                 //             center: Default::default(),
-//                 //             radius: 10.,
-//                 //             fill: Color32::TRANSPARENT,
-//                 //             stroke: Stroke::new(1., Color32::RED),
-//                 //         },
-//                 //         global_transform: GlobalTransform::from_xyz(
-//                 //             info.collision_point.x,
-//                 //             info.collision_point.y,
-//                 //             3.,
-//                 //         ),
-//                 //         ..Default::default()
-//                 //     })
-//                 //     .insert(DebugLine);
-//                 //
-//                 // c.spawn()
-//                 //     .insert_bundle(ShapeBundle {
-//                 //         shape: Shape::Circle {
-//                 //             center: Default::default(),
-//                 //             radius: 10.,
-//                 //             fill: Color32::TRANSPARENT,
-//                 //             stroke: Stroke::new(2., Color32::YELLOW),
-//                 //         },
-//                 //         // transform: Transform::from_xyz(pos_x, pos_x, 3.),
-//                 //         global_transform: GlobalTransform::from_xyz(
-//                 //             center.translation.x + pos_x,
-//                 //             center.translation.y + pos_y,
-//                 //             3.,
-//                 //         ),
-//                 //         ..Default::default()
-//                 //     })
-//                 //     .insert(DebugLine);
-//                 //
-//                 // Draw the line Player -> collision
-//                 // let ray_collide = c
-//                 //     .spawn()
-//                 //     .insert_bundle(ShapeBundle {
-//                 //         shape: Shape::LineSegment {
-//                 //             points: [
-//                 //                 // Pos2::new(0., 0.),
-//                 //                 Pos2::new(global.translation.x, global.translation.y),
-//                 //                 Pos2::new(info.collision_point.x, info.collision_point.y),
-//                 //             ],
-//                 //             stroke: Stroke::new(5., Color32::RED),
-//                 //         },
-//                 //         // transform: Transform::from_xyz(0., 0., 3.),
-//                 //         ..Default::default()
-//                 //     })
-//                 //     .insert(DebugLine)
-//                 //     .id();
-//                 // loop_debug_lines.push(ray_collide);
-//
-//                 // Draw the line Player -> Raycast
-//                 // let ray_line = c
-//                 //     .spawn()
-//                 //     .insert_bundle(ShapeBundle {
-//                 //         shape: Shape::LineSegment {
-//                 //             points: [Pos2::new(0., 0.), Pos2::new(pos_x, pos_y)],
-//                 //             stroke: Stroke::new(5., Color32::BLUE),
-//                 //         },
-//                 //         transform: Transform::from_xyz(0., 0., 3.),
-//                 //         // global_transform: GlobalTransform::from_xyz(0., 0., 2.),
-//                 //         ..Default::default()
-//                 //     })
-//                 //     .insert(DebugLine)
-//                 //     .id();
-//                 // loop_debug_lines.push(ray_line);
-//
-//                 // Draw the hit ray point
-//                 // let ray_line = c
-//                 //     .spawn()
-//                 //     .insert_bundle(ShapeBundle {
-//                 //         shape: Shape::Circle {
-//                 //             center: Default::default(),
-//                 //             radius: 2.,
-//                 //             fill: Color32::TRANSPARENT,
-//                 //             stroke: Stroke::new(5., Color32::YELLOW),
-//                 //         },
-//                 //         transform: Transform::from_xyz(
-//                 //             info.collision_point.x - local.translation.x,
-//                 //             info.collision_point.y - local.translation.y,
-//                 //             10.,
-//                 //         ),
-//                 //         // global_transform: GlobalTransform::from_xyz(
-//                 //         //     info.collision_point.x,
-//                 //         //     info.collision_point.y,
-//                 //         //     10.,
-//                 //         // ),
-//                 //         ..Default::default()
-//                 //     })
-//                 //     .insert(DebugLine)
-//                 //     .id();
-//                 // loop_debug_lines.push(ray_line);
-//
-//                 // Draw the target ray point
-//                 // let ray_target = c
-//                 //     .spawn()
-//                 //     .insert_bundle(ShapeBundle {
-//                 //         shape: Shape::Circle {
-//                 //             center: Default::default(),
-//                 //             radius: 2.,
-//                 //             fill: Color32::TRANSPARENT,
-//                 //             stroke: Stroke::new(5., Color32::GREEN),
-//                 //         },
-//                 //         transform: Transform::from_xyz(
-//                 //             pos_x + global.translation.x - local.translation.x,
-//                 //             pos_y + global.translation.y - local.translation.y,
-//                 //             10.,
-//                 //         ),
-//                 //         // global_transform: GlobalTransform::from_xyz(
-//                 //         //     info.collision_point.x,
-//                 //         //     info.collision_point.y,
-//                 //         //     10.,
-//                 //         // ),
-//                 //         ..Default::default()
-//                 //     })
-//                 //     .insert(DebugLine)
-//                 //     .id();
-//                 // loop_debug_lines.push(ray_target);
-//             }
-//         }
-//
-//         // c.entity(e_id)
-//         //     .insert_children(0, &[left_debug, right_debug, ray_circle]);
-//         // c.entity(e_id).insert_children(1, &loop_debug_lines);
-//     }
-// }
+    let project = map.as_ref().and_then(|map| projects.get(&map.project));
+    if let (Some(map), Some(LdtkProject(project))) = (&map, project) {
+        egui::Window::new("Levels").show(ui_context.ctx(), |ui| {
+            for (i, level) in project.levels.iter().enumerate() {
+                let label = if i == map.current_level() {
+                    format!("> {}", level.identifier)
+                } else {
+                    level.identifier.clone()
+                };
+                if ui.button(label).clicked() {
+                    change_level.send(ChangeLevel(LevelSelector::Index(i)));
+                }
+            }
+        });
+    }
+}
 
-/// Allows for the game to be quit via the ESC key
-fn quit_system(input: Res<Input<KeyCode>>, mut app: EventWriter<AppExit>) {
-    if input.just_pressed(KeyCode::Escape) {
+/// Allows for the game to be quit via the pause action, ESC by default
+fn quit_system(input: Res<Input<Action>>, mut app: EventWriter<AppExit>) {
+    if input.just_pressed(Action::Pause) {
         // Quit Game
         app.send(AppExit);
-        info!("Exiting cleanly via esc");
+        info!("Exiting cleanly via pause");
     }
 }
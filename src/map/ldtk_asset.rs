@@ -0,0 +1,84 @@
+use anyhow::anyhow;
+use bevy::asset::{AssetLoader, AssetPath, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::reflect::TypeUuid;
+use ldtk_rust::{Level, Project};
+use std::path::Path;
+
+/// A LDtk project loaded through the asset server, with any external levels already inlined
+#[derive(TypeUuid)]
+#[uuid = "5b0f2c8e-7d1a-4c53-9a0e-3f6d8b1e2a47"]
+pub struct LdtkProject(pub Project);
+
+/// An external level file. Projects inline their levels, these are only loaded so edits to
+/// the level files are noticed and patched into the project
+#[derive(TypeUuid)]
+#[uuid = "0c4e9a71-2b8d-4f36-8e15-6a9d3c7b5f02"]
+pub struct LdtkLevel(pub Level);
+
+#[derive(Default)]
+pub struct LdtkLoader;
+
+impl AssetLoader for LdtkLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut project: Project = serde_json::from_slice(bytes)?;
+            let mut dependencies = vec![];
+
+            // External levels are stored next to the project file
+            if project.external_levels {
+                let parent = load_context
+                    .path()
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .to_path_buf();
+
+                let mut levels = Vec::with_capacity(project.levels.len());
+                for level in project.levels.iter() {
+                    let level_path = level.external_rel_path.as_ref().ok_or_else(|| {
+                        anyhow!("Level {} is missing its external path", level.identifier)
+                    })?;
+                    let level_path = parent.join(level_path);
+                    let level_bytes = load_context.read_asset_bytes(&level_path).await?;
+                    let level: Level = serde_json::from_slice(&level_bytes)?;
+                    levels.push(level);
+                    dependencies.push(AssetPath::new(level_path, None));
+                }
+                project.levels = levels;
+            }
+
+            load_context.set_default_asset(
+                LoadedAsset::new(LdtkProject(project)).with_dependencies(dependencies),
+            );
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ldtk"]
+    }
+}
+
+#[derive(Default)]
+pub struct LdtkLevelLoader;
+
+impl AssetLoader for LdtkLevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let level: Level = serde_json::from_slice(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(LdtkLevel(level)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ldtkl"]
+    }
+}
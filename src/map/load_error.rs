@@ -0,0 +1,46 @@
+use crate::entity_class::indexer::IndexingError;
+use bevy::prelude::*;
+use thiserror::Error;
+
+/// Something in the LDtk project that could not be loaded. The offending tile, layer or entity is
+/// skipped and the rest of the level still spawns
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MapLoadError {
+    #[error("level {index} does not exist, the project has {levels} levels")]
+    MissingLevel { index: usize, levels: usize },
+    #[error("level {level} has no layer instances")]
+    MissingLayers { level: String },
+    #[error("layer {layer} of level {level} uses tileset {tileset_uid}, which is not loaded")]
+    MissingTileset {
+        level: String,
+        layer: String,
+        tileset_uid: i32,
+    },
+    #[error("layer {layer} of level {level} uses tile {tile_id}, which is not in its tileset")]
+    InvalidTile {
+        level: String,
+        layer: String,
+        tile_id: i64,
+    },
+    #[error("entity {entity} on layer {layer} of level {level} uses tileset {tileset_uid}, which is not loaded")]
+    MissingEntityTileset {
+        level: String,
+        layer: String,
+        entity: String,
+        tileset_uid: i32,
+    },
+    #[error(transparent)]
+    InvalidEntity(Box<IndexingError>),
+    #[error("field {field} of tileset {tileset} is malformed: {reason}")]
+    InvalidTilesetField {
+        tileset: String,
+        field: String,
+        reason: String,
+    },
+}
+
+/// Logs a load error and passes it on to anyone listening for `MapLoadError` events
+pub fn report_load_error(errors: &mut EventWriter<MapLoadError>, error: MapLoadError) {
+    error!("{}", error);
+    errors.send(error);
+}
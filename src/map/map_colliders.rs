@@ -1,32 +1,261 @@
-use crate::map::map_loader::MapTile;
+use crate::map::map_loader::{LevelScoped, MapTile};
 use crate::tags::WorldType;
 use crate::GameLayer;
 use bevy::prelude::*;
 use heron::{CollisionLayers, CollisionShape, RigidBody};
+use std::collections::HashMap;
 
 pub struct MapCollider;
 
+/// How wall tiles are turned into colliders
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum ColliderMode {
+    /// One collider per wall tile
+    PerTile,
+    /// Neighbouring wall tiles are merged into as few rectangles as possible
+    #[default]
+    Merged,
+}
+
+/// Which layers produce colliders, by layer identifier.
+/// Layers without an entry fall back to `default`
+pub struct ColliderLayers {
+    pub default: bool,
+    pub layers: HashMap<String, bool>,
+}
+
+impl Default for ColliderLayers {
+    fn default() -> Self {
+        ColliderLayers {
+            default: true,
+            layers: HashMap::new(),
+        }
+    }
+}
+
+impl ColliderLayers {
+    pub fn with_layer(mut self, layer: &str, colliders: bool) -> Self {
+        self.layers.insert(layer.to_string(), colliders);
+        self
+    }
+
+    pub fn has_colliders(&self, layer: &str) -> bool {
+        *self.layers.get(layer).unwrap_or(&self.default)
+    }
+}
+
+/// A rectangle of grid cells, `x` and `y` being its top left cell
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct GridRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+pub fn wall_collider(half_extends: Vec3) -> (RigidBody, CollisionShape, CollisionLayers) {
+    (
+        RigidBody::Static,
+        CollisionShape::Cuboid {
+            half_extends,
+            border_radius: None,
+        },
+        CollisionLayers::none()
+            .with_group(GameLayer::World)
+            .with_masks(&[GameLayer::Projectile, GameLayer::Enemy, GameLayer::Player]),
+    )
+}
+
 pub fn generate_colliders_for_map_tiles(
     mut c: Commands,
+    mode: Res<ColliderMode>,
+    collider_layers: Res<ColliderLayers>,
     tiles: Query<(Entity, &MapTile), Without<MapCollider>>,
 ) {
-    // TODO restrict to only WORLD tiles NOT BG tiles
+    if *mode != ColliderMode::PerTile {
+        return;
+    }
+
     for (eid, tile) in tiles.iter() {
-        if tile.world_type == WorldType::Wall {
+        if tile.has_tag(WorldType::Wall) && collider_layers.has_colliders(&tile.layer) {
             c.entity(eid)
                 .insert(MapCollider)
-                .insert(RigidBody::Static)
-                .insert(CollisionShape::Cuboid {
-                    half_extends: (tile.size / 2.).extend(tile.depth as f32),
-                    border_radius: None,
-                })
-                .insert(
-                    CollisionLayers::none()
-                        .with_group(GameLayer::World)
-                        .with_masks(&[GameLayer::Projectile, GameLayer::Enemy, GameLayer::Player]),
-                );
+                .insert_bundle(wall_collider((tile.size / 2.).extend(tile.depth)));
         } else {
             c.entity(eid).insert(MapCollider);
         }
     }
 }
+
+/// Rebuilds the merged wall colliders whenever new map tiles are spawned
+pub fn generate_merged_colliders(
+    mut c: Commands,
+    mode: Res<ColliderMode>,
+    collider_layers: Res<ColliderLayers>,
+    added: Query<(), Added<MapTile>>,
+    tiles: Query<(&MapTile, &Transform)>,
+    colliders: Query<Entity, (With<MapCollider>, Without<MapTile>)>,
+) {
+    if *mode != ColliderMode::Merged || added.iter().next().is_none() {
+        return;
+    }
+
+    for eid in colliders.iter() {
+        c.entity(eid).despawn();
+    }
+
+    // Each layer has its own grid
+    let mut layers: HashMap<i32, Vec<(&MapTile, Vec3)>> = HashMap::new();
+    for (tile, transform) in tiles.iter() {
+        if tile.has_tag(WorldType::Wall) && collider_layers.has_colliders(&tile.layer) {
+            layers
+                .entry(tile.layer_uid)
+                .or_default()
+                .push((tile, transform.translation));
+        }
+    }
+
+    for walls in layers.values() {
+        let min = walls.iter().fold(IVec2::splat(i32::MAX), |min, (tile, _)| {
+            min.min(tile.grid_pos)
+        });
+        let max = walls.iter().fold(IVec2::splat(i32::MIN), |max, (tile, _)| {
+            max.max(tile.grid_pos)
+        });
+        let width = (max.x - min.x + 1) as usize;
+        let height = (max.y - min.y + 1) as usize;
+
+        let mut cells = vec![false; width * height];
+        let mut positions = HashMap::new();
+        for (tile, translation) in walls.iter() {
+            let cell = tile.grid_pos - min;
+            cells[cell.y as usize * width + cell.x as usize] = true;
+            positions.insert(cell, *translation);
+        }
+
+        let tile = walls[0].0;
+        for rect in merge_cells(&cells, width, height) {
+            // The collider sits halfway between the centres of its corner tiles
+            let first = positions[&IVec2::new(rect.x, rect.y)];
+            let last = positions[&IVec2::new(rect.x + rect.width - 1, rect.y + rect.height - 1)];
+            let size = tile.size * Vec2::new(rect.width as f32, rect.height as f32);
+
+            c.spawn()
+                .insert(MapCollider)
+                .insert(LevelScoped)
+                .insert(Transform::from_translation((first + last) / 2.))
+                .insert(GlobalTransform::default())
+                .insert_bundle(wall_collider((size / 2.).extend(tile.depth)));
+        }
+    }
+}
+
+/// Greedily merges the solid cells of a row-major grid into rectangles,
+/// growing each rectangle right as far as it can, then down
+pub fn merge_cells(cells: &[bool], width: usize, height: usize) -> Vec<GridRect> {
+    let mut used = vec![false; cells.len()];
+    let mut rects = vec![];
+    let free = |used: &[bool], x: usize, y: usize| cells[y * width + x] && !used[y * width + x];
+
+    for y in 0..height {
+        for x in 0..width {
+            if !free(&used, x, y) {
+                continue;
+            }
+
+            let mut rect_width = 1;
+            while x + rect_width < width && free(&used, x + rect_width, y) {
+                rect_width += 1;
+            }
+
+            let mut rect_height = 1;
+            while y + rect_height < height
+                && (x..x + rect_width).all(|cx| free(&used, cx, y + rect_height))
+            {
+                rect_height += 1;
+            }
+
+            for cy in y..y + rect_height {
+                for cx in x..x + rect_width {
+                    used[cy * width + cx] = true;
+                }
+            }
+
+            rects.push(GridRect {
+                x: x as i32,
+                y: y as i32,
+                width: rect_width as i32,
+                height: rect_height as i32,
+            });
+        }
+    }
+
+    rects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(rows: &[&str]) -> (Vec<bool>, usize, usize) {
+        let cells = rows
+            .iter()
+            .flat_map(|row| row.chars().map(|c| c == '#'))
+            .collect();
+        (cells, rows[0].len(), rows.len())
+    }
+
+    fn merge(rows: &[&str]) -> Vec<GridRect> {
+        let (cells, width, height) = grid(rows);
+        let rects = merge_cells(&cells, width, height);
+
+        // Every solid cell is covered exactly once
+        let covered: i32 = rects.iter().map(|r| r.width * r.height).sum();
+        assert_eq!(covered as usize, cells.iter().filter(|c| **c).count());
+        rects
+    }
+
+    #[test]
+    fn empty_grid_has_no_rects() {
+        assert!(merge(&["...", "...", "..."]).is_empty());
+    }
+
+    #[test]
+    fn solid_grid_is_one_rect() {
+        assert_eq!(
+            merge(&["###", "###", "###"]),
+            vec![GridRect {
+                x: 0,
+                y: 0,
+                width: 3,
+                height: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn single_row_and_column() {
+        assert_eq!(merge(&["#####"]).len(), 1);
+        assert_eq!(merge(&["#", "#", "#", "#"]).len(), 1);
+    }
+
+    #[test]
+    fn l_shape_is_two_rects() {
+        assert_eq!(merge(&["#..", "#..", "###"]).len(), 2);
+    }
+
+    #[test]
+    fn room_outline_is_four_rects() {
+        assert_eq!(merge(&["#####", "#...#", "#...#", "#####"]).len(), 4);
+    }
+
+    #[test]
+    fn checkerboard_cannot_merge() {
+        assert_eq!(merge(&["#.#", ".#.", "#.#"]).len(), 5);
+    }
+
+    #[test]
+    fn separate_blocks() {
+        assert_eq!(merge(&["##..##", "##..##", "......", "..##.."]).len(), 3);
+    }
+}
@@ -1,55 +1,210 @@
-use crate::map::map_colliders::generate_colliders_for_map_tiles;
-use crate::map::utils::convert_to_world;
-use crate::tags::{world_type_from_str, WorldType};
+use crate::map::ldtk_asset::{LdtkLevel, LdtkLevelLoader, LdtkLoader, LdtkProject};
+use crate::map::load_error::{report_load_error, MapLoadError};
+use crate::map::map_colliders::{
+    generate_colliders_for_map_tiles, generate_merged_colliders, ColliderLayers, ColliderMode,
+};
+use crate::map::tile_baking::{bake_tile_chunks, BakeTile, PendingTileBake, TileBaking};
+use crate::map::tile_grid::build_tile_grid;
+use crate::map::utils::{MapCoords, MapOrigin};
+use crate::tags::{world_type_from_str, Player, WorldType};
+use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
-use ldtk_rust::Project;
+use ldtk_rust::{Project, TileInstance, TilesetDefinition};
 use serde::Deserialize;
 use serde_json::Value;
 use std::collections::HashMap;
 
 pub struct MapPlugin;
+/// Path of the LDtk project, relative to the assets folder
 pub struct MapLocation(pub String);
 pub struct MapScale(pub f32);
 
 pub struct MapTile {
+    /// Identifier of the layer the tile came from
+    pub layer: String,
+    pub layer_uid: i32,
+    pub grid_pos: IVec2,
     pub size: Vec2,
     pub depth: f32,
+    /// The most solid of the tile's tags
     pub world_type: WorldType,
+    pub tags: Vec<WorldType>,
+}
+
+impl MapTile {
+    pub fn has_tag(&self, world_type: WorldType) -> bool {
+        self.tags.contains(&world_type)
+    }
 }
 
 pub struct Map {
-    pub ldtk_map: Project,
+    pub project: Handle<LdtkProject>,
     current_level: usize,
     reload: bool,
 }
 
+impl Map {
+    pub fn current_level(&self) -> usize {
+        self.current_level
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LevelSelector {
+    Index(usize),
+    Identifier(String),
+}
+
+impl LevelSelector {
+    /// Resolve the selector against a loaded project
+    pub fn find(&self, project: &Project) -> Option<usize> {
+        match self {
+            LevelSelector::Index(i) if *i < project.levels.len() => Some(*i),
+            LevelSelector::Index(_) => None,
+            LevelSelector::Identifier(id) => project
+                .levels
+                .iter()
+                .position(|level| &level.identifier == id),
+        }
+    }
+}
+
+/// Level to spawn first, defaults to the first level in the project
+pub struct StartLevel(pub LevelSelector);
+
+/// Send to despawn the current level and spawn the selected one in its place
+pub struct ChangeLevel(pub LevelSelector);
+
+/// Sent once a level's tiles and entities have been spawned, they exist from `PostUpdate` on
+pub struct LevelSpawned;
+
+/// Tags anything that belongs to the currently loaded level, so it is cleaned up on a level change
+pub struct LevelScoped;
+
+/// Where the player stood before the project was hot reloaded
+struct ReloadedPlayerPosition(Option<Vec3>);
+
+#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub enum MapFunctions {
+    ChangeLevel,
+    Spawn,
+}
+
 pub struct MapAssets {
     sprite_sheets: HashMap<i32, Handle<TextureAtlas>>,
     entity_materials: HashMap<i32, Handle<ColorMaterial>>,
-    // tilemap_enum_defs: HashMap<i32, HashMap<i64, Vec<WorldType>>>,
+    tilemap_enum_tags: HashMap<i32, HashMap<i64, Vec<WorldType>>>,
     tilemap_custom_data: HashMap<i32, HashMap<i64, String>>,
+    int_grid_world_types: HashMap<i32, HashMap<i64, WorldType>>,
+    tileset_grids: HashMap<i32, TilesetGrid>,
+    tileset_textures: HashMap<i32, Handle<Texture>>,
+}
+
+impl MapAssets {
+    /// Enum tags take priority, tilesets without them fall back to their custom data
+    fn tile_world_types(&self, tileset_uid: i32, tile_id: i64) -> Vec<WorldType> {
+        if let Some(tags) = self
+            .tilemap_enum_tags
+            .get(&tileset_uid)
+            .and_then(|tags| tags.get(&tile_id))
+        {
+            return tags.clone();
+        }
+
+        self.tilemap_custom_data
+            .get(&tileset_uid)
+            .and_then(|data| data.get(&tile_id))
+            .and_then(|data| world_type_from_str(data))
+            .into_iter()
+            .collect()
+    }
 }
 
 pub struct MapEntity {
     pub name: String,
+    /// Level and layer identifiers the entity was placed on
+    pub level: String,
+    pub layer: String,
+    pub def_uid: i32,
     pub grid_pos: Vec2,
+    /// Width and height in world units
+    pub size: Vec2,
+    /// Coordinates of the entity's layer, whose grid its cell and point fields are in
+    pub coords: MapCoords,
     pub fields: HashMap<String, Option<Value>>,
 }
 
 struct MapLayerInfo {
-    _grid_width: i32,
-    _grid_height: i32,
+    grid_width: i32,
+    grid_height: i32,
     grid_size: i32,
     depth: i32,
-    px_width: f32,
-    px_height: f32,
+    px_offset: Vec2,
+    coords: MapCoords,
+}
+
+/// Layout of a tileset image, used to find a tile's atlas index from its pixel position
+#[derive(Clone)]
+pub struct TilesetGrid {
+    pub tile_size: i64,
+    pub spacing: i64,
+    pub padding: i64,
+    pub columns: i64,
+    pub rows: i64,
+}
+
+impl TilesetGrid {
+    fn tile_index(&self, px_x: i64, px_y: i64) -> u32 {
+        let step = self.tile_size + self.spacing;
+        let column = (px_x - self.padding) / step;
+        let row = (px_y - self.padding) / step;
+        (row * self.columns + column) as u32
+    }
+
+    pub fn contains(&self, tile_id: i64) -> bool {
+        tile_id >= 0 && tile_id < self.columns * self.rows
+    }
+
+    /// Top left pixel of a tile within the tileset image
+    pub fn tile_px(&self, tile_id: i64) -> (i64, i64) {
+        let step = self.tile_size + self.spacing;
+        (
+            self.padding + tile_id % self.columns * step,
+            self.padding + tile_id / self.columns * step,
+        )
+    }
 }
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_system(init_map.system())
-            .add_system(update_map.system())
-            .add_system(generate_colliders_for_map_tiles.system());
+        app.add_asset::<LdtkProject>()
+            .init_asset_loader::<LdtkLoader>()
+            .add_asset::<LdtkLevel>()
+            .init_asset_loader::<LdtkLevelLoader>()
+            .add_event::<ChangeLevel>()
+            .add_event::<MapLoadError>()
+            .add_event::<LevelSpawned>()
+            .insert_resource(ReloadedPlayerPosition(None))
+            .init_resource::<ColliderMode>()
+            .init_resource::<ColliderLayers>()
+            .init_resource::<TileBaking>()
+            .init_resource::<MapOrigin>()
+            .add_startup_system(init_map.system())
+            // Runs ahead of the update stage, so the new map assets are in place before spawning
+            .add_system_to_stage(CoreStage::PreUpdate, on_project_loaded.system())
+            .add_system_to_stage(CoreStage::PreUpdate, patch_external_levels.system())
+            .add_system(change_level.system().label(MapFunctions::ChangeLevel))
+            .add_system(
+                update_map
+                    .system()
+                    .label(MapFunctions::Spawn)
+                    .after(MapFunctions::ChangeLevel),
+            )
+            .add_system(restore_player_after_reload.system())
+            .add_system(generate_colliders_for_map_tiles.system())
+            .add_system(generate_merged_colliders.system())
+            .add_system(bake_tile_chunks.system())
+            .add_system_to_stage(CoreStage::PostUpdate, build_tile_grid.system());
     }
 }
 
@@ -62,65 +217,157 @@ struct MapEnumMapping {
     tile_ids: Vec<i64>,
 }
 
-#[derive(Deserialize, Debug)]
-struct MapEnumTileIds {
-    tile_ids: Vec<i32>,
+pub fn init_map(mut c: Commands, map_path: Res<MapLocation>, asset_server: Res<AssetServer>) {
+    c.insert_resource(Map {
+        project: asset_server.load(map_path.0.as_str()),
+        current_level: 0,
+        reload: false,
+    });
 }
 
-pub fn init_map(
-    mut c: Commands,
-    map_path: Res<MapLocation>,
-    asset_server: Res<AssetServer>,
-    mut textures_atlases: ResMut<Assets<TextureAtlas>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-) {
-    // TODO use bevy asset system to load the path
-    let map = Map {
-        ldtk_map: ldtk_rust::Project::new(map_path.0.clone()),
-        current_level: 0,
-        reload: true,
+// Convert enum data to []id -> enum[] instead of []enum -> []id.
+// Entries that can't be read are reported and skipped, the rest of the tileset keeps its tags
+fn parse_enum_tags(
+    tileset: &TilesetDefinition,
+    errors: &mut EventWriter<MapLoadError>,
+) -> HashMap<i64, Vec<WorldType>> {
+    let mut tags: HashMap<i64, Vec<WorldType>> = HashMap::new();
+    for tag in tileset.enum_tags.iter() {
+        let tag = tag
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone().unwrap_or(Value::Null)))
+            .collect();
+        let mapping: MapEnumMapping = match serde_json::from_value(Value::Object(tag)) {
+            Ok(mapping) => mapping,
+            Err(e) => {
+                report_load_error(
+                    errors,
+                    MapLoadError::InvalidTilesetField {
+                        tileset: tileset.identifier.clone(),
+                        field: "enumTags".into(),
+                        reason: e.to_string(),
+                    },
+                );
+                continue;
+            }
+        };
+
+        // Tags from enums that aren't about the world, e.g. character types, are not ours to use
+        let world_type = match world_type_from_str(&mapping.enum_value_id) {
+            Some(world_type) => world_type,
+            None => continue,
+        };
+        for tile_id in mapping.tile_ids {
+            tags.entry(tile_id).or_default().push(world_type);
+        }
+    }
+    tags
+}
+
+fn parse_custom_data(tileset: &TilesetDefinition) -> Result<HashMap<i64, String>, MapLoadError> {
+    let invalid = |reason: String| MapLoadError::InvalidTilesetField {
+        tileset: tileset.identifier.clone(),
+        field: "customData".into(),
+        reason,
     };
+    let mut data = HashMap::new();
+    for entry in tileset.custom_data.iter() {
+        let tile_id = entry
+            .get("tileId")
+            .and_then(|tile_id| tile_id.as_ref())
+            .and_then(Value::as_i64)
+            .ok_or_else(|| invalid(format!("{:?} has no tile id", entry)))?;
+        let value = match entry.get("data") {
+            Some(Some(Value::String(value))) => value.clone(),
+            value => {
+                return Err(invalid(format!(
+                    "data of tile {} is not a string: {:?}",
+                    tile_id, value
+                )))
+            }
+        };
+        data.insert(tile_id, value);
+    }
+    Ok(data)
+}
 
+/// The most solid of a tile's tags, tiles without any are air
+fn primary_world_type(tags: &[WorldType]) -> WorldType {
+    tags.iter()
+        .copied()
+        .max_by_key(WorldType::solidity)
+        .unwrap_or(WorldType::Air)
+}
+
+/// Builds the assets of every tileset and entity in the project. Atlases and materials of the
+/// `previous` project are updated in place, so hot reloads don't leave the old ones behind
+fn load_map_assets(
+    project: &Project,
+    previous: Option<&MapAssets>,
+    asset_server: &AssetServer,
+    textures_atlases: &mut Assets<TextureAtlas>,
+    materials: &mut Assets<ColorMaterial>,
+    errors: &mut EventWriter<MapLoadError>,
+) -> MapAssets {
     let mut map_assets = MapAssets {
         sprite_sheets: HashMap::new(),
         entity_materials: HashMap::new(),
+        tilemap_enum_tags: HashMap::new(),
         tilemap_custom_data: HashMap::new(),
+        int_grid_world_types: HashMap::new(),
+        tileset_grids: HashMap::new(),
+        tileset_textures: HashMap::new(),
     };
 
     // Load all tilesets
-    for tileset in map.ldtk_map.defs.tilesets.iter() {
+    for tileset in project.defs.tilesets.iter() {
         let id = tileset.uid;
         let name = &tileset.identifier;
         let sprite_path = &tileset.rel_path;
-        let tile_size = Vec2::new(tileset.px_wid as f32, tileset.px_hei as f32);
         let texture_handle = asset_server.load(&sprite_path[..]);
-        let atlas = TextureAtlas::from_grid(
+        let grid = TilesetGrid {
+            tile_size: tileset.tile_grid_size,
+            spacing: tileset.spacing,
+            padding: tileset.padding,
+            columns: tileset.c_wid,
+            rows: tileset.c_hei,
+        };
+
+        // Lay the atlas out in the same order as LDtk's tile ids, skipping padding and spacing
+        map_assets
+            .tileset_textures
+            .insert(id as i32, texture_handle.clone());
+        let mut atlas = TextureAtlas::new_empty(
             texture_handle,
-            tile_size,
-            tileset.c_wid as usize,
-            tileset.c_hei as usize,
+            Vec2::new(tileset.px_wid as f32, tileset.px_hei as f32),
         );
-        let texture_atlas_handle = textures_atlases.add(atlas);
-
-        // Convert enum data to []id -> enum[] instead of []enum -> []id
-
-        let mut data_map = HashMap::new();
-        for enum_map in tileset.custom_data.iter() {
-            let enum_label = enum_map.get("tileId").unwrap().as_ref().unwrap();
-            let enum_value = enum_map.get("data").unwrap().as_ref().unwrap();
-
-            let tile_id = match enum_label {
-                Value::Number(s) => s.as_i64().unwrap(),
-                v => panic!("Can not parse {}", v),
-            };
-            let value = match enum_value {
-                Value::String(s) => s,
-                v => panic!("Can not parse {}", v),
-            };
+        for row in 0..tileset.c_hei {
+            for column in 0..tileset.c_wid {
+                let min = Vec2::new(
+                    (grid.padding + column * (grid.tile_size + grid.spacing)) as f32,
+                    (grid.padding + row * (grid.tile_size + grid.spacing)) as f32,
+                );
+                atlas.add_texture(bevy::sprite::Rect {
+                    min,
+                    max: min + Vec2::splat(grid.tile_size as f32),
+                });
+            }
+        }
+        let texture_atlas_handle = match previous.and_then(|p| p.sprite_sheets.get(&(id as i32))) {
+            Some(handle) => textures_atlases.set(handle.id, atlas),
+            None => textures_atlases.add(atlas),
+        };
+        map_assets.tileset_grids.insert(id as i32, grid);
 
-            data_map.insert(tile_id, value.clone());
+        map_assets
+            .tilemap_enum_tags
+            .insert(id as i32, parse_enum_tags(tileset, errors));
+        match parse_custom_data(tileset) {
+            Ok(data) => {
+                map_assets.tilemap_custom_data.insert(id as i32, data);
+            }
+            Err(e) => report_load_error(errors, e),
         }
-        map_assets.tilemap_custom_data.insert(id as i32, data_map);
 
         info!("Loading tileset {} from {}...", name, sprite_path);
         map_assets
@@ -128,103 +375,404 @@ pub fn init_map(
             .insert(id as i32, texture_atlas_handle);
     }
 
+    // Map IntGrid values to world types through their identifiers, e.g. "Wall"
+    for layer in project
+        .defs
+        .layers
+        .iter()
+        .filter(|layer| layer.layer_definition_type == "IntGrid")
+    {
+        let mut world_types = HashMap::new();
+        for value in layer.int_grid_values.iter() {
+            match value.identifier.as_deref().and_then(world_type_from_str) {
+                Some(world_type) => {
+                    world_types.insert(value.value, world_type);
+                }
+                None => warn!(
+                    "IntGrid value {} of layer {} has no known world type, skipping it",
+                    value.value, layer.identifier
+                ),
+            }
+        }
+        map_assets
+            .int_grid_world_types
+            .insert(layer.uid as i32, world_types);
+    }
+
     // Load all entity assets
-    for entity in map.ldtk_map.defs.entities.iter() {
+    for entity in project.defs.entities.iter() {
         let id = entity.uid;
         let name = &entity.identifier;
         // let size = Vec3::new(entity.width as f32, entity.height as f32, 0.0);
         // Skip first char, since its the '#'
         let color_code = &entity.color.clone()[1..];
-        let color = match Color::hex(&color_code) {
+        let color = match Color::hex(color_code) {
             Ok(t) => t,
             Err(e) => {
                 error!("Failed to parse color, defaulting to blue, {:?}", e);
                 Color::BLUE
             }
         };
-        let material = materials.add(ColorMaterial::from(color));
+        let material = match previous.and_then(|p| p.entity_materials.get(&(id as i32))) {
+            Some(handle) => materials.set(handle.id, ColorMaterial::from(color)),
+            None => materials.add(ColorMaterial::from(color)),
+        };
 
         info!("Loading entity {}..", name);
         map_assets.entity_materials.insert(id as i32, material);
     }
 
-    c.insert_resource(map);
-    c.insert_resource(map_assets)
+    map_assets
+}
+
+/// Spawns the first level once the project has loaded, and respawns the current level in place
+/// whenever the project file changes on disk
+#[allow(clippy::too_many_arguments)]
+fn on_project_loaded(
+    mut c: Commands,
+    mut events: EventReader<AssetEvent<LdtkProject>>,
+    mut map: ResMut<Map>,
+    projects: Res<Assets<LdtkProject>>,
+    map_assets: Option<Res<MapAssets>>,
+    start_level: Option<Res<StartLevel>>,
+    asset_server: Res<AssetServer>,
+    mut textures_atlases: ResMut<Assets<TextureAtlas>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut reloaded_player: ResMut<ReloadedPlayerPosition>,
+    mut errors: EventWriter<MapLoadError>,
+    scoped: Query<Entity, With<LevelScoped>>,
+    player: Query<&Transform, With<Player>>,
+) {
+    for event in events.iter() {
+        let (handle, reloaded) = match event {
+            AssetEvent::Created { handle } => (handle, false),
+            AssetEvent::Modified { handle } => (handle, true),
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != map.project {
+            continue;
+        }
+        let project = match projects.get(handle) {
+            Some(LdtkProject(project)) => project,
+            None => continue,
+        };
+
+        if reloaded {
+            info!("Map changed on disk, respawning the current level");
+            reloaded_player.0 = player.single().ok().map(|t| t.translation);
+            for eid in scoped.iter() {
+                c.entity(eid).despawn_recursive();
+            }
+
+            // The current level may have been deleted
+            if map.current_level >= project.levels.len() {
+                map.current_level = 0;
+            }
+        } else if let Some(start_level) = &start_level {
+            match start_level.0.find(project) {
+                Some(index) => map.current_level = index,
+                None => error!(
+                    "No level matches {:?}, starting from the first level",
+                    start_level.0
+                ),
+            }
+        }
+
+        c.insert_resource(load_map_assets(
+            project,
+            map_assets.as_deref(),
+            &asset_server,
+            &mut textures_atlases,
+            &mut materials,
+            &mut errors,
+        ));
+        map.reload = true;
+    }
+}
+
+/// Copies external level files that changed on disk into the project. The project is then
+/// modified too, which respawns the level like any other hot reload
+fn patch_external_levels(
+    mut events: EventReader<AssetEvent<LdtkLevel>>,
+    levels: Res<Assets<LdtkLevel>>,
+    map: Res<Map>,
+    mut projects: ResMut<Assets<LdtkProject>>,
+) {
+    for event in events.iter() {
+        // Levels are already inlined when they are first loaded
+        let level = match event {
+            AssetEvent::Modified { handle } => match levels.get(handle) {
+                Some(LdtkLevel(level)) => level,
+                None => continue,
+            },
+            _ => continue,
+        };
+        let project = match projects.get_mut(&map.project) {
+            Some(LdtkProject(project)) => project,
+            None => continue,
+        };
+        if let Some(stale) = project
+            .levels
+            .iter_mut()
+            .find(|stale| stale.uid == level.uid)
+        {
+            info!("Level {} changed on disk", level.identifier);
+            *stale = level.clone();
+        }
+    }
 }
 
+/// Puts the player back where they were before a hot reload, instead of at their start location
+fn restore_player_after_reload(
+    mut reloaded_player: ResMut<ReloadedPlayerPosition>,
+    mut player: Query<&mut Transform, Added<Player>>,
+) {
+    if let Some(position) = reloaded_player.0 {
+        for mut transform in player.iter_mut() {
+            transform.translation = position;
+            reloaded_player.0 = None;
+        }
+    }
+}
+
+pub fn change_level(
+    mut c: Commands,
+    mut events: EventReader<ChangeLevel>,
+    mut map: ResMut<Map>,
+    projects: Res<Assets<LdtkProject>>,
+    scoped: Query<Entity, With<LevelScoped>>,
+) {
+    // Only the most recent request matters if several arrive in one frame
+    let selector = match events.iter().last() {
+        Some(ChangeLevel(selector)) => selector,
+        None => return,
+    };
+
+    let project = match projects.get(&map.project) {
+        Some(LdtkProject(project)) => project,
+        None => {
+            error!("Can not change level before the map has loaded");
+            return;
+        }
+    };
+
+    let index = match selector.find(project) {
+        Some(index) => index,
+        None => {
+            error!("Can not change level, no level matches {:?}", selector);
+            return;
+        }
+    };
+
+    info!("Changing level to {}", project.levels[index].identifier);
+    for eid in scoped.iter() {
+        c.entity(eid).despawn_recursive();
+    }
+
+    map.current_level = index;
+    map.reload = true;
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn update_map(
     mut c: Commands,
     mut map: ResMut<Map>,
-    assets: Res<MapAssets>,
+    projects: Res<Assets<LdtkProject>>,
+    assets: Option<Res<MapAssets>>,
     scale: Res<MapScale>,
+    origin: Res<MapOrigin>,
+    baking: Res<TileBaking>,
+    collider_layers: Res<ColliderLayers>,
+    mut errors: EventWriter<MapLoadError>,
+    mut spawned: EventWriter<LevelSpawned>,
 ) {
     // Only run if work needs to be done
     if !map.reload {
         return;
     }
+    let (project, assets) = match (projects.get(&map.project), assets) {
+        (Some(LdtkProject(project)), Some(assets)) => (project, assets),
+        _ => return,
+    };
+
+    // A reloaded project may have lost every level
+    let level = match project.levels.get(map.current_level) {
+        Some(level) => level,
+        None => {
+            report_load_error(
+                &mut errors,
+                MapLoadError::MissingLevel {
+                    index: map.current_level,
+                    levels: project.levels.len(),
+                },
+            );
+            map.reload = false;
+            return;
+        }
+    };
+    info!("Spawning Level {}", level.identifier);
 
-    for (layer_z, layer) in map.ldtk_map.levels[map.current_level]
+    // Layers can have their own grid size, but they all share the level's origin.
+    // Gameplay works on the finest of them, so no layer has cells smaller than the grid's
+    let grid_size = level
         .layer_instances
-        .as_ref()
-        .unwrap()
         .iter()
-        .enumerate()
-        .rev()
-    {
-        let tileset_uid = layer.tileset_def_uid.unwrap_or(-1) as i32;
-        // let layer_uid = layer.layer_def_uid as i32;
+        .flatten()
+        .map(|layer| layer.grid_size as i32)
+        .min()
+        .unwrap_or(project.default_grid_size as i32);
+    let level_coords = MapCoords {
+        px_size: Vec2::new(level.px_wid as f32, level.px_hei as f32),
+        grid_size,
+        scale: scale.0,
+        origin: *origin,
+    };
+    c.insert_resource(level_coords);
+
+    let layers = match &level.layer_instances {
+        Some(layers) => layers,
+        None => {
+            report_load_error(
+                &mut errors,
+                MapLoadError::MissingLayers {
+                    level: level.identifier.clone(),
+                },
+            );
+            map.reload = false;
+            return;
+        }
+    };
+
+    for (layer_z, layer) in layers.iter().enumerate().rev() {
+        let tileset_uid = layer
+            .override_tileset_uid
+            .or(layer.tileset_def_uid)
+            .unwrap_or(-1) as i32;
         let layer_name = &layer.identifier;
         let layer_type = &layer.layer_instance_type[..];
 
         info!("Spawning Layer {} of type {}", layer_name, layer_type);
 
         let layer_info = MapLayerInfo {
-            _grid_width: layer.c_wid as i32,
-            _grid_height: layer.c_hei as i32,
+            grid_width: layer.c_wid as i32,
+            grid_height: layer.c_hei as i32,
             grid_size: layer.grid_size as i32,
             depth: (25 - layer_z as i32) * 2,
-            px_width: layer.c_wid as f32 * (layer.grid_size as f32 * scale.0),
-            px_height: layer.c_hei as f32 * (layer.grid_size as f32 * scale.0),
+            px_offset: Vec2::new(
+                layer.px_total_offset_x as f32,
+                layer.px_total_offset_y as f32,
+            ),
+            coords: MapCoords {
+                grid_size: layer.grid_size as i32,
+                ..level_coords
+            },
         };
 
         match layer_type {
-            "Tiles" => {
-                for tile in layer.grid_tiles.iter() {
-                    // TODO flip controls
-                    // TODO bake the static layers
+            "Tiles" | "AutoLayer" => {
+                let (texture_atlas, grid) = match (
+                    assets.sprite_sheets.get(&tileset_uid),
+                    assets.tileset_grids.get(&tileset_uid),
+                ) {
+                    (Some(texture_atlas), Some(grid)) => (texture_atlas, grid),
+                    _ => {
+                        report_load_error(
+                            &mut errors,
+                            MapLoadError::MissingTileset {
+                                level: level.identifier.clone(),
+                                layer: layer_name.clone(),
+                                tileset_uid,
+                            },
+                        );
+                        continue;
+                    }
+                };
+                let tiles = if layer_type == "Tiles" {
+                    &layer.grid_tiles
+                } else {
+                    &layer.auto_layer_tiles
+                };
+                let tiles = valid_tiles(tiles, grid, &level.identifier, layer_name, &mut errors);
+
+                // Layers nothing interacts with are baked into a few large sprites
+                if baking.enabled && !collider_layers.has_colliders(layer_name) {
+                    if let Some(texture) = assets.tileset_textures.get(&tileset_uid) {
+                        spawn_baked_chunks(
+                            &mut c,
+                            texture,
+                            grid,
+                            &tiles,
+                            &layer_info,
+                            baking.chunk_size,
+                            scale.0,
+                        );
+                        continue;
+                    }
+                }
+
+                for tile in tiles {
+                    let tags = assets.tile_world_types(tileset_uid, tile.t);
+                    spawn_tile(&mut c, texture_atlas, tile, &layer_info, scale.0).insert(MapTile {
+                        layer: layer_name.clone(),
+                        layer_uid: layer.layer_def_uid as i32,
+                        grid_pos: IVec2::new(tile.px[0] as i32, tile.px[1] as i32)
+                            / layer_info.grid_size,
+                        size: layer_info.coords.tile_size(),
+                        depth: layer_info.depth as f32,
+                        world_type: primary_world_type(&tags),
+                        tags,
+                    });
+                }
+            }
+            "IntGrid" => {
+                // Auto-layer rules paint on top of the grid, those tiles are purely visual
+                if let (Some(texture_atlas), Some(grid)) = (
+                    assets.sprite_sheets.get(&tileset_uid),
+                    assets.tileset_grids.get(&tileset_uid),
+                ) {
+                    let tiles = valid_tiles(
+                        &layer.auto_layer_tiles,
+                        grid,
+                        &level.identifier,
+                        layer_name,
+                        &mut errors,
+                    );
+                    for tile in tiles {
+                        spawn_tile(&mut c, texture_atlas, tile, &layer_info, scale.0);
+                    }
+                }
+
+                let world_types = match assets
+                    .int_grid_world_types
+                    .get(&(layer.layer_def_uid as i32))
+                {
+                    Some(world_types) => world_types,
+                    None => continue,
+                };
+                for (i, value) in layer.int_grid_csv.iter().enumerate() {
+                    let world_type = match world_types.get(value) {
+                        Some(world_type) => *world_type,
+                        None => continue,
+                    };
+                    let cell_x = i as i32 % layer_info.grid_width;
+                    let cell_y = i as i32 / layer_info.grid_width;
 
                     c.spawn()
-                        .insert_bundle(SpriteSheetBundle {
-                            transform: Transform {
-                                translation: convert_to_world(
-                                    layer_info.px_width,
-                                    layer_info.px_height,
-                                    layer_info.grid_size,
-                                    scale.0,
-                                    tile.px[0] as i32,
-                                    tile.px[1] as i32,
-                                    layer_info.depth,
-                                ),
-                                rotation: Default::default(),
-                                scale: Vec3::splat(scale.0),
-                            },
-                            sprite: TextureAtlasSprite::new(tile.t as u32),
-                            texture_atlas: assets.sprite_sheets.get(&tileset_uid).unwrap().clone(),
-                            ..Default::default()
-                        })
+                        .insert(LevelScoped)
                         .insert(MapTile {
-                            size: Vec2::new(layer_info.px_width, layer_info.px_height) * scale.0,
+                            layer: layer_name.clone(),
+                            layer_uid: layer.layer_def_uid as i32,
+                            grid_pos: IVec2::new(cell_x, cell_y),
+                            size: layer_info.coords.tile_size(),
                             depth: layer_info.depth as f32,
-                            world_type: world_type_from_str(
-                                assets
-                                    .tilemap_custom_data
-                                    .get(&tileset_uid)
-                                    .unwrap_or(&HashMap::new())
-                                    .get(&tile.t)
-                                    .unwrap_or(&String::new()),
-                            )
-                            .unwrap_or(WorldType::Air),
-                        });
+                            world_type,
+                            tags: vec![world_type],
+                        })
+                        .insert(Transform::from_translation(cell_to_world(
+                            IVec2::new(cell_x, cell_y) * layer_info.grid_size,
+                            &layer_info,
+                        )))
+                        .insert(GlobalTransform::default());
                 }
             }
             "Entities" => {
@@ -239,44 +787,65 @@ pub fn update_map(
                         fields.insert(field_name, field_value);
                     }
 
+                    // LDtk places entities by their pivot, sprites are drawn from their centre
+                    let size = Vec2::new(entity.width as f32, entity.height as f32);
+                    let pivot = Vec2::new(entity.pivot[0] as f32, entity.pivot[1] as f32);
+                    let centre = Vec2::new(entity.px[0] as f32, entity.px[1] as f32)
+                        + (Vec2::splat(0.5) - pivot) * size;
+
                     let transform = Transform {
-                        translation: convert_to_world(
-                            layer_info.px_width,
-                            layer_info.px_height,
-                            layer_info.grid_size,
-                            scale.0,
-                            entity.px[0] as i32,
-                            entity.px[1] as i32,
-                            layer_info.depth,
-                        ),
+                        translation: px_to_world(centre, &layer_info),
                         rotation: Default::default(),
                         scale: Vec3::splat(scale.0),
                     };
 
-                    if let Some(tile) = &entity.tile {
-                        c.spawn()
-                            .insert_bundle(SpriteSheetBundle {
-                                transform,
-                                sprite: TextureAtlasSprite::new((tile.src_rect[0] / 64) as u32),
-                                texture_atlas: assets
-                                    .sprite_sheets
-                                    .get(&(tile.tileset_uid as i32))
-                                    .unwrap()
-                                    .clone(),
-                                ..Default::default()
-                            })
-                            .insert(MapEntity {
-                                name: name.to_string(),
-                                grid_pos: Vec2::new(entity.grid[0] as f32, entity.grid[1] as f32),
-                                fields,
-                            });
+                    let mut entity_commands = c.spawn();
+                    entity_commands.insert(LevelScoped).insert(MapEntity {
+                        name: name.to_string(),
+                        level: level.identifier.clone(),
+                        layer: layer_name.clone(),
+                        def_uid: entity.def_uid as i32,
+                        grid_pos: Vec2::new(entity.grid[0] as f32, entity.grid[1] as f32),
+                        size: size * scale.0,
+                        coords: layer_info.coords,
+                        fields,
+                    });
+
+                    // Entities without a usable tile still spawn, just without a sprite
+                    let sprite = entity.tile.as_ref().and_then(|tile| {
+                        let tileset_uid = tile.tileset_uid as i32;
+                        match (
+                            assets.sprite_sheets.get(&tileset_uid),
+                            assets.tileset_grids.get(&tileset_uid),
+                        ) {
+                            (Some(texture_atlas), Some(grid)) => Some((
+                                texture_atlas,
+                                grid.tile_index(tile.src_rect[0], tile.src_rect[1]),
+                            )),
+                            _ => {
+                                report_load_error(
+                                    &mut errors,
+                                    MapLoadError::MissingEntityTileset {
+                                        level: level.identifier.clone(),
+                                        layer: layer_name.clone(),
+                                        entity: name.clone(),
+                                        tileset_uid,
+                                    },
+                                );
+                                None
+                            }
+                        }
+                    });
+
+                    if let Some((texture_atlas, sprite_index)) = sprite {
+                        entity_commands.insert_bundle(SpriteSheetBundle {
+                            transform,
+                            sprite: TextureAtlasSprite::new(sprite_index),
+                            texture_atlas: texture_atlas.clone(),
+                            ..Default::default()
+                        });
                     } else {
-                        c.spawn()
-                            .insert(MapEntity {
-                                name: name.to_string(),
-                                grid_pos: Vec2::new(entity.grid[0] as f32, entity.grid[1] as f32),
-                                fields,
-                            })
+                        entity_commands
                             .insert(transform)
                             .insert(GlobalTransform::default());
                     }
@@ -292,4 +861,123 @@ pub fn update_map(
     }
 
     map.reload = false;
+    spawned.send(LevelSpawned);
+}
+
+/// Converts a pixel position within the layer into a world position
+fn px_to_world(px: Vec2, layer_info: &MapLayerInfo) -> Vec3 {
+    layer_info
+        .coords
+        .px_to_world(px + layer_info.px_offset, layer_info.depth as f32)
+}
+
+/// Converts the top left pixel of a grid cell into the world position of the cell's centre
+fn cell_to_world(px: IVec2, layer_info: &MapLayerInfo) -> Vec3 {
+    px_to_world(
+        px.as_f32() + Vec2::splat(layer_info.grid_size as f32 / 2.),
+        layer_info,
+    )
+}
+
+/// Filters out tiles that aren't in their tileset, reporting each of them
+fn valid_tiles<'a>(
+    tiles: &'a [TileInstance],
+    grid: &TilesetGrid,
+    level: &str,
+    layer: &str,
+    errors: &mut EventWriter<MapLoadError>,
+) -> Vec<&'a TileInstance> {
+    tiles
+        .iter()
+        .filter(|tile| {
+            let valid = grid.contains(tile.t);
+            if !valid {
+                report_load_error(
+                    errors,
+                    MapLoadError::InvalidTile {
+                        level: level.into(),
+                        layer: layer.into(),
+                        tile_id: tile.t,
+                    },
+                );
+            }
+            valid
+        })
+        .collect()
+}
+
+fn spawn_baked_chunks(
+    c: &mut Commands,
+    texture: &Handle<Texture>,
+    grid: &TilesetGrid,
+    tiles: &[&TileInstance],
+    layer_info: &MapLayerInfo,
+    chunk_size: i32,
+    scale: f32,
+) {
+    let chunk_px = chunk_size * layer_info.grid_size;
+    let mut chunks: HashMap<IVec2, Vec<BakeTile>> = HashMap::new();
+    for tile in tiles.iter() {
+        let px = IVec2::new(tile.px[0] as i32, tile.px[1] as i32);
+        let chunk = px / chunk_px;
+        chunks.entry(chunk).or_default().push(BakeTile {
+            px: px - chunk * chunk_px,
+            tile_id: tile.t,
+            flip: tile.f,
+        });
+    }
+
+    let layer_px = IVec2::new(layer_info.grid_width, layer_info.grid_height) * layer_info.grid_size;
+    for (chunk, tiles) in chunks {
+        // Chunks along the right and bottom edges are cut to the layer size
+        let origin = chunk * chunk_px;
+        let size = (layer_px - origin).min(IVec2::splat(chunk_px));
+
+        c.spawn()
+            .insert(LevelScoped)
+            .insert(Transform {
+                translation: px_to_world(origin.as_f32() + size.as_f32() / 2., layer_info),
+                rotation: Default::default(),
+                scale: Vec3::splat(scale),
+            })
+            .insert(GlobalTransform::default())
+            .insert(PendingTileBake {
+                texture: texture.clone(),
+                grid: grid.clone(),
+                size,
+                tiles,
+            });
+    }
+}
+
+fn spawn_tile<'a, 'b>(
+    c: &'b mut Commands<'a>,
+    texture_atlas: &Handle<TextureAtlas>,
+    tile: &TileInstance,
+    layer_info: &MapLayerInfo,
+    scale: f32,
+) -> EntityCommands<'a, 'b> {
+    // Bit 0 of `f` flips the tile on x, bit 1 on y
+    let flip = Vec2::new(
+        if tile.f & 1 != 0 { -1. } else { 1. },
+        if tile.f & 2 != 0 { -1. } else { 1. },
+    );
+
+    let mut tile_commands = c.spawn();
+    tile_commands
+        .insert_bundle(SpriteSheetBundle {
+            transform: Transform {
+                translation: cell_to_world(
+                    IVec2::new(tile.px[0] as i32, tile.px[1] as i32),
+                    layer_info,
+                ),
+                rotation: Default::default(),
+                scale: (flip * scale).extend(scale),
+            },
+            sprite: TextureAtlasSprite::new(tile.t as u32),
+            texture_atlas: texture_atlas.clone(),
+            ..Default::default()
+        })
+        .insert(LevelScoped);
+    tile_commands
 }
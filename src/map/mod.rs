@@ -1,3 +1,8 @@
+pub mod ldtk_asset;
+pub mod load_error;
 pub mod map_colliders;
 pub mod map_loader;
+pub mod pathfinding;
+pub mod tile_baking;
+pub mod tile_grid;
 pub mod utils;
@@ -0,0 +1,300 @@
+use crate::map::tile_grid::TileGrid;
+use bevy::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+const STRAIGHT_COST: i32 = 10;
+const DIAGONAL_COST: i32 = 14;
+
+const STRAIGHT: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+const DIAGONAL: [(i32, i32); 4] = [(1, -1), (1, 1), (-1, 1), (-1, -1)];
+
+/// When a path may cut across a cell diagonally
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Diagonals {
+    Never,
+    /// Only when both cells beside the diagonal are free, so walls aren't clipped at corners
+    NoCornerCutting,
+    Always,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PathOptions {
+    pub diagonals: Diagonals,
+    /// Free cells needed on every side of a cell before something can stand in it
+    pub clearance: i32,
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        PathOptions {
+            diagonals: Diagonals::NoCornerCutting,
+            clearance: 0,
+        }
+    }
+}
+
+impl PathOptions {
+    /// Clearance for something reaching `radius` out from its centre, on cells `tile_size` wide
+    pub fn with_radius(mut self, radius: f32, tile_size: f32) -> Self {
+        // The cell it stands in already covers half a tile either way
+        self.clearance = ((radius - tile_size / 2.) / tile_size).ceil().max(0.) as i32;
+        self
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct OpenCell {
+    estimate: i32,
+    cost: i32,
+    cell: (i32, i32),
+}
+
+// Reversed so the heap pops the cheapest estimate first
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .cmp(&self.estimate)
+            .then_with(|| self.cost.cmp(&other.cost))
+            .then_with(|| self.cell.cmp(&other.cell))
+    }
+}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Whether something with `clearance` fits in `cell` without touching anything impassable.
+/// Closed doors are planned through, followers open them when they get there
+pub fn walkable(grid: &TileGrid, cell: IVec2, clearance: i32) -> bool {
+    (-clearance..=clearance)
+        .all(|y| (-clearance..=clearance).all(|x| grid.is_passable(cell + IVec2::new(x, y))))
+}
+
+fn heuristic(from: IVec2, to: IVec2, diagonals: Diagonals) -> i32 {
+    let delta = (to - from).abs();
+    match diagonals {
+        Diagonals::Never => STRAIGHT_COST * (delta.x + delta.y),
+        _ => {
+            STRAIGHT_COST * (delta.x + delta.y)
+                + (DIAGONAL_COST - 2 * STRAIGHT_COST) * delta.x.min(delta.y)
+        }
+    }
+}
+
+/// A* from `from` to `to` over the grid cells. The path leaves out the starting cell and ends
+/// at `to`, None if `to` can't be reached
+pub fn find_path_cells(
+    grid: &TileGrid,
+    from: IVec2,
+    to: IVec2,
+    options: PathOptions,
+) -> Option<Vec<IVec2>> {
+    let coords = &grid.coords;
+    if !coords.in_bounds(from) || !walkable(grid, to, options.clearance) {
+        return None;
+    }
+
+    let size = coords.grid_dimensions();
+    let index = |cell: IVec2| (cell.y * size.x + cell.x) as usize;
+    let mut costs = vec![i32::MAX; (size.x * size.y) as usize];
+    let mut came_from: Vec<Option<IVec2>> = vec![None; costs.len()];
+    let mut open = BinaryHeap::new();
+
+    costs[index(from)] = 0;
+    open.push(OpenCell {
+        estimate: heuristic(from, to, options.diagonals),
+        cost: 0,
+        cell: (from.x, from.y),
+    });
+
+    let free = |cell: IVec2| coords.in_bounds(cell) && walkable(grid, cell, options.clearance);
+
+    while let Some(OpenCell { cost, cell, .. }) = open.pop() {
+        let cell = IVec2::new(cell.0, cell.1);
+        if cell == to {
+            let mut path = vec![cell];
+            while let Some(previous) = came_from[index(*path.last().unwrap())] {
+                path.push(previous);
+            }
+            // The starting cell is where we already are
+            path.pop();
+            path.reverse();
+            return Some(path);
+        }
+        // A cheaper way here was found after this one was queued
+        if cost > costs[index(cell)] {
+            continue;
+        }
+
+        let straight = STRAIGHT
+            .iter()
+            .map(|(x, y)| (IVec2::new(*x, *y), STRAIGHT_COST));
+        let diagonal = DIAGONAL
+            .iter()
+            .filter(|(x, y)| match options.diagonals {
+                Diagonals::Never => false,
+                Diagonals::NoCornerCutting => {
+                    free(cell + IVec2::new(*x, 0)) && free(cell + IVec2::new(0, *y))
+                }
+                Diagonals::Always => true,
+            })
+            .map(|(x, y)| (IVec2::new(*x, *y), DIAGONAL_COST));
+
+        for (step, step_cost) in straight.chain(diagonal) {
+            let next = cell + step;
+            if !free(next) {
+                continue;
+            }
+            let next_cost = cost + step_cost;
+            if next_cost < costs[index(next)] {
+                costs[index(next)] = next_cost;
+                came_from[index(next)] = Some(cell);
+                open.push(OpenCell {
+                    estimate: next_cost + heuristic(next, to, options.diagonals),
+                    cost: next_cost,
+                    cell: (next.x, next.y),
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// Waypoints in world space from `from` to `to`, passing through the centre of each cell
+/// on the way and finishing exactly on `to`
+pub fn find_path(grid: &TileGrid, from: Vec3, to: Vec3, options: PathOptions) -> Option<Vec<Vec2>> {
+    let coords = &grid.coords;
+    let mut cells = find_path_cells(
+        grid,
+        coords.world_to_grid(from),
+        coords.world_to_grid(to),
+        options,
+    )?;
+    // The last cell's centre is replaced by the goal itself
+    cells.pop();
+
+    let mut waypoints = cells
+        .into_iter()
+        .map(|cell| coords.grid_to_world(cell, 0.).truncate())
+        .collect::<Vec<_>>();
+    waypoints.push(to.truncate());
+    Some(waypoints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::tile_grid::grid_from_rows as grid;
+
+    fn options(diagonals: Diagonals, clearance: i32) -> PathOptions {
+        PathOptions {
+            diagonals,
+            clearance,
+        }
+    }
+
+    #[test]
+    fn walks_around_walls() {
+        let grid = grid(&["....", ".##.", "...."]);
+        let path = find_path_cells(
+            &grid,
+            IVec2::new(0, 1),
+            IVec2::new(3, 1),
+            options(Diagonals::Never, 0),
+        )
+        .unwrap();
+        assert_eq!(path.len(), 5);
+        assert_eq!(path.last(), Some(&IVec2::new(3, 1)));
+        assert!(path.iter().all(|cell| !grid.is_solid(*cell)));
+    }
+
+    #[test]
+    fn diagonals_shorten_paths_but_dont_cut_corners() {
+        let open = grid(&["...", "...", "..."]);
+        let path = find_path_cells(
+            &open,
+            IVec2::ZERO,
+            IVec2::new(2, 2),
+            options(Diagonals::NoCornerCutting, 0),
+        )
+        .unwrap();
+        assert_eq!(path, vec![IVec2::new(1, 1), IVec2::new(2, 2)]);
+
+        let corner = grid(&[".#", ".."]);
+        let cut = find_path_cells(
+            &corner,
+            IVec2::ZERO,
+            IVec2::ONE,
+            options(Diagonals::Always, 0),
+        );
+        assert_eq!(cut.unwrap().len(), 1);
+        let around = find_path_cells(
+            &corner,
+            IVec2::ZERO,
+            IVec2::ONE,
+            options(Diagonals::NoCornerCutting, 0),
+        );
+        assert_eq!(around.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn paths_go_through_closed_doors() {
+        let grid = grid(&["..#..", "..D..", "..#.."]);
+        let path = find_path_cells(
+            &grid,
+            IVec2::new(0, 1),
+            IVec2::new(4, 1),
+            options(Diagonals::NoCornerCutting, 0),
+        )
+        .unwrap();
+        assert!(path.contains(&IVec2::new(2, 1)));
+        // Doors still block sight while they are closed
+        assert!(!grid.line_of_sight(IVec2::new(0, 1), IVec2::new(4, 1)));
+    }
+
+    #[test]
+    fn clearance_keeps_big_creatures_out_of_narrow_gaps() {
+        let grid = grid(&[
+            ".....", ".....", ".....", "##.##", ".....", ".....", ".....",
+        ]);
+        let from = IVec2::new(2, 1);
+        let to = IVec2::new(2, 5);
+        assert!(find_path_cells(&grid, from, to, options(Diagonals::Never, 0)).is_some());
+        assert!(find_path_cells(&grid, from, to, options(Diagonals::Never, 1)).is_none());
+    }
+
+    #[test]
+    fn unreachable_goals_have_no_path() {
+        let grid = grid(&["..#..", "..#..", "..#.."]);
+        let path = find_path_cells(
+            &grid,
+            IVec2::ZERO,
+            IVec2::new(4, 0),
+            options(Diagonals::Always, 0),
+        );
+        assert!(path.is_none());
+    }
+
+    #[test]
+    fn world_paths_end_on_the_goal() {
+        let grid = grid(&["...."]);
+        let from = grid.coords.grid_to_world(IVec2::ZERO, 0.);
+        let to = grid.coords.grid_to_world(IVec2::new(3, 0), 0.) + Vec3::new(3., 0., 0.);
+        let path = find_path(&grid, from, to, PathOptions::default()).unwrap();
+        assert_eq!(path.len(), 3);
+        assert_eq!(path.last(), Some(&to.truncate()));
+    }
+
+    #[test]
+    fn radius_rounds_up_to_whole_cells() {
+        let options = PathOptions::default();
+        assert_eq!(options.with_radius(28., 64.).clearance, 0);
+        assert_eq!(options.with_radius(10., 16.).clearance, 1);
+        assert_eq!(options.with_radius(30., 16.).clearance, 2);
+    }
+}
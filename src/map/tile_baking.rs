@@ -0,0 +1,209 @@
+use crate::map::map_loader::TilesetGrid;
+use bevy::prelude::*;
+use bevy::render::texture::{Extent3d, TextureDimension};
+
+/// Controls how static tile layers are composited into textures when a level loads.
+/// Only layers that don't produce colliders are baked
+pub struct TileBaking {
+    pub enabled: bool,
+    /// Width and height of a baked chunk, in tiles
+    pub chunk_size: i32,
+}
+
+impl Default for TileBaking {
+    fn default() -> Self {
+        TileBaking {
+            enabled: true,
+            chunk_size: 16,
+        }
+    }
+}
+
+pub struct BakeTile {
+    /// Top left pixel of the tile, relative to the chunk
+    pub px: IVec2,
+    pub tile_id: i64,
+    pub flip: i64,
+}
+
+/// A chunk of tiles waiting for its tileset texture to load before it can be baked
+pub struct PendingTileBake {
+    pub texture: Handle<Texture>,
+    pub grid: TilesetGrid,
+    /// Size of the chunk in pixels
+    pub size: IVec2,
+    pub tiles: Vec<BakeTile>,
+}
+
+pub fn bake_tile_chunks(
+    mut c: Commands,
+    mut textures: ResMut<Assets<Texture>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    pending: Query<(Entity, &PendingTileBake, &Transform)>,
+) {
+    for (eid, bake, transform) in pending.iter() {
+        let tileset = match textures.get(&bake.texture) {
+            Some(tileset) => tileset,
+            None => continue,
+        };
+
+        if tileset.format.pixel_size() != 4 {
+            error!(
+                "Can not bake tiles from a {:?} tileset, only 8 bit RGBA is supported",
+                tileset.format
+            );
+            c.entity(eid).remove::<PendingTileBake>();
+            continue;
+        }
+
+        let baked = Texture::new(
+            Extent3d::new(bake.size.x as u32, bake.size.y as u32, 1),
+            TextureDimension::D2,
+            composite(tileset, bake),
+            tileset.format,
+        );
+        let material = materials.add(textures.add(baked).into());
+
+        c.entity(eid)
+            .remove::<PendingTileBake>()
+            .insert_bundle(SpriteBundle {
+                material,
+                transform: *transform,
+                ..Default::default()
+            });
+    }
+}
+
+/// Draws every tile of the chunk on top of each other, in order
+fn composite(tileset: &Texture, bake: &PendingTileBake) -> Vec<u8> {
+    let width = bake.size.x as usize;
+    let height = bake.size.y as usize;
+    let tileset_width = tileset.size.width as usize;
+    let tile_size = bake.grid.tile_size as usize;
+    let mut data = vec![0; width * height * 4];
+
+    for tile in bake.tiles.iter() {
+        let (src_x, src_y) = bake.grid.tile_px(tile.tile_id);
+        for y in 0..tile_size {
+            for x in 0..tile_size {
+                let dst_x = tile.px.x as usize + x;
+                let dst_y = tile.px.y as usize + y;
+                if dst_x >= width || dst_y >= height {
+                    continue;
+                }
+
+                // Flipped tiles read their pixels mirrored
+                let flip_x = if tile.flip & 1 != 0 {
+                    tile_size - 1 - x
+                } else {
+                    x
+                };
+                let flip_y = if tile.flip & 2 != 0 {
+                    tile_size - 1 - y
+                } else {
+                    y
+                };
+                let src = ((src_y as usize + flip_y) * tileset_width + src_x as usize + flip_x) * 4;
+                let dst = (dst_y * width + dst_x) * 4;
+                if src + 4 > tileset.data.len() {
+                    continue;
+                }
+
+                blend(&mut data[dst..dst + 4], &tileset.data[src..src + 4]);
+            }
+        }
+    }
+
+    data
+}
+
+/// Alpha blends `src` over `dst`
+fn blend(dst: &mut [u8], src: &[u8]) {
+    let alpha = src[3] as u32;
+    for (dst, src) in dst.iter_mut().zip(src).take(3) {
+        *dst = ((*src as u32 * alpha + *dst as u32 * (255 - alpha)) / 255) as u8;
+    }
+    dst[3] = (alpha + dst[3] as u32 * (255 - alpha) / 255) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::texture::TextureFormat;
+
+    // A 2x1 tileset of 2px tiles, each pixel has a unique red value
+    fn tileset() -> Texture {
+        let data = (0..8u8)
+            .flat_map(|i| vec![i * 10, 0, 0, 255])
+            .collect::<Vec<_>>();
+        Texture::new(
+            Extent3d::new(4, 2, 1),
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    fn grid() -> TilesetGrid {
+        TilesetGrid {
+            tile_size: 2,
+            spacing: 0,
+            padding: 0,
+            columns: 2,
+            rows: 1,
+        }
+    }
+
+    fn reds(data: &[u8]) -> Vec<u8> {
+        data.chunks(4).map(|px| px[0]).collect()
+    }
+
+    #[test]
+    fn copies_tiles_into_place() {
+        let bake = PendingTileBake {
+            texture: Default::default(),
+            grid: grid(),
+            size: IVec2::new(4, 2),
+            tiles: vec![
+                BakeTile {
+                    px: IVec2::new(0, 0),
+                    tile_id: 1,
+                    flip: 0,
+                },
+                BakeTile {
+                    px: IVec2::new(2, 0),
+                    tile_id: 0,
+                    flip: 0,
+                },
+            ],
+        };
+
+        assert_eq!(
+            reds(&composite(&tileset(), &bake)),
+            vec![20, 30, 0, 10, 60, 70, 40, 50]
+        );
+    }
+
+    #[test]
+    fn flips_tiles() {
+        let bake = PendingTileBake {
+            texture: Default::default(),
+            grid: grid(),
+            size: IVec2::new(2, 2),
+            tiles: vec![BakeTile {
+                px: IVec2::new(0, 0),
+                tile_id: 0,
+                flip: 3,
+            }],
+        };
+
+        assert_eq!(reds(&composite(&tileset(), &bake)), vec![50, 40, 10, 0]);
+    }
+
+    #[test]
+    fn transparent_pixels_keep_what_is_below() {
+        let mut dst = [10, 20, 30, 255];
+        blend(&mut dst, &[200, 200, 200, 0]);
+        assert_eq!(dst, [10, 20, 30, 255]);
+    }
+}
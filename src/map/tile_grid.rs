@@ -0,0 +1,327 @@
+use crate::map::map_colliders::ColliderLayers;
+use crate::map::map_loader::{LevelSpawned, MapTile};
+use crate::map::utils::MapCoords;
+use crate::tags::WorldType;
+use bevy::prelude::*;
+
+const NEIGHBOURS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TileCell {
+    pub entity: Entity,
+    pub world_type: WorldType,
+}
+
+/// What is in each grid cell of the current level, rebuilt whenever a level is spawned
+pub struct TileGrid {
+    pub coords: MapCoords,
+    size: IVec2,
+    cells: Vec<Option<TileCell>>,
+}
+
+impl TileGrid {
+    pub fn new(coords: MapCoords) -> Self {
+        let size = coords.grid_dimensions();
+        TileGrid {
+            coords,
+            size,
+            cells: vec![None; (size.x * size.y) as usize],
+        }
+    }
+
+    fn index(&self, cell: IVec2) -> Option<usize> {
+        if self.coords.in_bounds(cell) {
+            Some((cell.y * self.size.x + cell.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Stores a tile in a cell, unless the cell already holds something more solid
+    pub fn insert(&mut self, cell: IVec2, tile: TileCell) {
+        if let Some(i) = self.index(cell) {
+            let replace = match &self.cells[i] {
+                Some(existing) => tile.world_type.solidity() > existing.world_type.solidity(),
+                None => true,
+            };
+            if replace {
+                self.cells[i] = Some(tile);
+            }
+        }
+    }
+
+    /// Overwrites a cell whatever it held, for tiles that change at runtime
+    pub fn set(&mut self, cell: IVec2, tile: TileCell) {
+        if let Some(i) = self.index(cell) {
+            self.cells[i] = Some(tile);
+        }
+    }
+
+    pub fn get(&self, cell: IVec2) -> Option<&TileCell> {
+        self.index(cell).and_then(|i| self.cells[i].as_ref())
+    }
+
+    pub fn at_world(&self, world: Vec3) -> Option<&TileCell> {
+        self.get(self.coords.world_to_grid(world))
+    }
+
+    /// Empty cells are air, cells outside the level are nothing
+    pub fn world_type(&self, cell: IVec2) -> WorldType {
+        match self.get(cell) {
+            Some(tile) => tile.world_type,
+            None if self.coords.in_bounds(cell) => WorldType::Air,
+            None => WorldType::Nothing,
+        }
+    }
+
+    /// Whether a cell stops movement and sight: walls, closed doors and the edge of the level.
+    /// Tiles tagged `Nothing` are decoration without a meaning of their own, they don't block
+    pub fn is_solid(&self, cell: IVec2) -> bool {
+        !self.coords.in_bounds(cell)
+            || matches!(self.world_type(cell), WorldType::Wall | WorldType::Door)
+    }
+
+    /// Whether something could walk through a cell. Unlike `is_solid`, closed doors don't stop
+    /// anyone, they can be opened on the way. Locked doors are in the grid as walls
+    pub fn is_passable(&self, cell: IVec2) -> bool {
+        self.coords.in_bounds(cell) && self.world_type(cell) != WorldType::Wall
+    }
+
+    /// The in-bounds cells sharing an edge with `cell`
+    pub fn neighbours(&self, cell: IVec2) -> impl Iterator<Item = IVec2> + '_ {
+        NEIGHBOURS
+            .iter()
+            .map(move |(x, y)| cell + IVec2::new(*x, *y))
+            .filter(move |neighbour| self.coords.in_bounds(*neighbour))
+    }
+
+    /// Walks a line between two cells, true if no solid cell is crossed.
+    /// The starting cell is not checked
+    pub fn line_of_sight(&self, from: IVec2, to: IVec2) -> bool {
+        line_cells(from, to)
+            .skip(1)
+            .all(|cell| !self.is_solid(cell))
+    }
+
+    pub fn line_of_sight_world(&self, from: Vec3, to: Vec3) -> bool {
+        self.line_of_sight(
+            self.coords.world_to_grid(from),
+            self.coords.world_to_grid(to),
+        )
+    }
+}
+
+/// Cells on a Bresenham line from `from` to `to`, both included
+pub fn line_cells(from: IVec2, to: IVec2) -> impl Iterator<Item = IVec2> {
+    let delta = to - from;
+    let step = delta.signum();
+    let dx = delta.x.abs();
+    let dy = -delta.y.abs();
+    let mut error = dx + dy;
+    let mut cell = from;
+    let mut done = false;
+
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let current = cell;
+        if cell == to {
+            done = true;
+            return Some(current);
+        }
+        let e2 = 2 * error;
+        if e2 >= dy {
+            error += dy;
+            cell.x += step.x;
+        }
+        if e2 <= dx {
+            error += dx;
+            cell.y += step.y;
+        }
+        Some(current)
+    })
+}
+
+/// The cells a tile of `size` centred on `centre` overlaps. Layers can be on a coarser grid
+/// than the level's, so one tile may cover several cells
+pub fn covered_cells(coords: &MapCoords, centre: Vec3, size: Vec2) -> impl Iterator<Item = IVec2> {
+    // Pulled in a little, so edges resting on the next cell don't count as covering it
+    let half = (size / 2. - Vec2::splat(0.01)).max(Vec2::ZERO).extend(0.);
+    let a = coords.world_to_grid(centre - half);
+    let b = coords.world_to_grid(centre + half);
+    let (min, max) = (a.min(b), a.max(b));
+    (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+}
+
+pub fn build_tile_grid(
+    mut c: Commands,
+    mut spawned: EventReader<LevelSpawned>,
+    coords: Option<Res<MapCoords>>,
+    collider_layers: Res<ColliderLayers>,
+    tiles: Query<(Entity, &MapTile, &Transform)>,
+) {
+    if spawned.iter().next().is_none() {
+        return;
+    }
+    let coords = match coords {
+        Some(coords) => coords,
+        None => return,
+    };
+
+    let mut grid = TileGrid::new(*coords);
+    for (eid, tile, transform) in tiles.iter() {
+        // Decoration layers don't get in anyone's way
+        if !collider_layers.has_colliders(&tile.layer) {
+            continue;
+        }
+        for cell in covered_cells(&coords, transform.translation, tile.size) {
+            grid.insert(
+                cell,
+                TileCell {
+                    entity: eid,
+                    world_type: tile.world_type,
+                },
+            );
+        }
+    }
+    c.insert_resource(grid);
+}
+
+/// A grid of 16px cells drawn as text: `#` for walls, `D` for doors and anything else for air
+#[cfg(test)]
+pub fn grid_from_rows(rows: &[&str]) -> TileGrid {
+    use crate::map::utils::MapOrigin;
+
+    let mut grid = TileGrid::new(MapCoords {
+        px_size: Vec2::new(rows[0].len() as f32, rows.len() as f32) * 16.,
+        grid_size: 16,
+        scale: 1.,
+        origin: MapOrigin::TopLeft,
+    });
+    for (y, row) in rows.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            let world_type = match c {
+                '#' => WorldType::Wall,
+                'D' => WorldType::Door,
+                _ => continue,
+            };
+            grid.insert(
+                IVec2::new(x as i32, y as i32),
+                TileCell {
+                    entity: Entity::new((y * row.len() + x) as u32),
+                    world_type,
+                },
+            );
+        }
+    }
+    grid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::grid_from_rows as grid;
+    use super::*;
+
+    #[test]
+    fn looks_up_cells_by_world_position() {
+        let grid = grid(&["...", ".#.", "..."]);
+        let centre = grid.coords.grid_to_world(IVec2::new(1, 1), 0.);
+        assert_eq!(grid.at_world(centre).unwrap().entity, Entity::new(4));
+        assert_eq!(grid.world_type(IVec2::new(0, 0)), WorldType::Air);
+        assert_eq!(grid.world_type(IVec2::new(-1, 0)), WorldType::Nothing);
+    }
+
+    #[test]
+    fn walls_win_over_other_layers() {
+        let mut grid = grid(&["#"]);
+        grid.insert(
+            IVec2::ZERO,
+            TileCell {
+                entity: Entity::new(9),
+                world_type: WorldType::Air,
+            },
+        );
+        assert_eq!(grid.get(IVec2::ZERO).unwrap().entity, Entity::new(0));
+    }
+
+    #[test]
+    fn set_overrides_more_solid_cells() {
+        let mut grid = grid(&["D"]);
+        grid.set(
+            IVec2::ZERO,
+            TileCell {
+                entity: Entity::new(0),
+                world_type: WorldType::Air,
+            },
+        );
+        assert!(!grid.is_solid(IVec2::ZERO));
+    }
+
+    #[test]
+    fn untagged_tiles_dont_block() {
+        let mut grid = grid(&["..."]);
+        grid.insert(
+            IVec2::new(1, 0),
+            TileCell {
+                entity: Entity::new(1),
+                world_type: WorldType::Nothing,
+            },
+        );
+        assert!(!grid.is_solid(IVec2::new(1, 0)));
+        assert!(grid.line_of_sight(IVec2::new(0, 0), IVec2::new(2, 0)));
+    }
+
+    #[test]
+    fn neighbours_stay_in_bounds() {
+        let grid = grid(&["...", "...", "..."]);
+        assert_eq!(grid.neighbours(IVec2::new(1, 1)).count(), 4);
+        assert_eq!(grid.neighbours(IVec2::new(0, 0)).count(), 2);
+        assert_eq!(grid.neighbours(IVec2::new(2, 1)).count(), 3);
+    }
+
+    #[test]
+    fn line_includes_both_ends() {
+        let cells = line_cells(IVec2::new(0, 0), IVec2::new(3, 1)).collect::<Vec<_>>();
+        assert_eq!(cells.first(), Some(&IVec2::new(0, 0)));
+        assert_eq!(cells.last(), Some(&IVec2::new(3, 1)));
+        assert_eq!(cells.len(), 4);
+        assert_eq!(line_cells(IVec2::ONE, IVec2::ONE).count(), 1);
+    }
+
+    #[test]
+    fn walls_and_doors_block_sight() {
+        let grid = grid(&[".....", "..#..", ".....", "..D.."]);
+        assert!(!grid.line_of_sight(IVec2::new(0, 1), IVec2::new(4, 1)));
+        assert!(!grid.line_of_sight(IVec2::new(0, 3), IVec2::new(4, 3)));
+        assert!(grid.line_of_sight(IVec2::new(0, 2), IVec2::new(4, 2)));
+        assert!(grid.line_of_sight(IVec2::new(0, 0), IVec2::new(4, 0)));
+        // Symmetric around the wall
+        assert!(!grid.line_of_sight(IVec2::new(4, 1), IVec2::new(0, 1)));
+    }
+
+    #[test]
+    fn tiles_cover_every_cell_under_them() {
+        let coords = grid(&["....", "....", "....", "...."]).coords;
+        // A 32px tile centred on the corner shared by the four top left cells
+        let centre = coords.px_to_world(Vec2::splat(16.), 0.);
+        let cells = covered_cells(&coords, centre, Vec2::splat(32.)).collect::<Vec<_>>();
+        assert_eq!(
+            cells,
+            vec![
+                IVec2::new(0, 0),
+                IVec2::new(1, 0),
+                IVec2::new(0, 1),
+                IVec2::new(1, 1)
+            ]
+        );
+        let one = coords.grid_to_world(IVec2::new(2, 2), 0.);
+        assert_eq!(covered_cells(&coords, one, Vec2::splat(16.)).count(), 1);
+    }
+
+    #[test]
+    fn sight_ends_at_the_edge_of_the_level() {
+        let grid = grid(&["..."]);
+        assert!(!grid.line_of_sight(IVec2::new(0, 0), IVec2::new(5, 0)));
+    }
+}
@@ -1,23 +1,160 @@
 use bevy::prelude::*;
 
-// LDtk 0,0 is the top left, moving -x,-y
-// Bevy 0,0 is the center of the screen
-// Need to offset and flip y
-pub fn convert_to_world(
-    _width: f32,
-    _height: f32,
-    _grid_size: i32,
-    _scale: f32,
-    x: i32,
-    y: i32,
-    z: i32,
-) -> Vec3 {
-    let world_x = x as f32;
-    let world_y = -y as f32;
-    let world_z = z as f32;
-    // info!(
-    //     "Spawning tile at {:?}",
-    //     Vec3::new(world_x, world_y, world_z)
-    // );
-    Vec3::new(world_x, world_y, world_z)
+/// Where the top left corner of a LDtk level ends up in the world
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum MapOrigin {
+    /// The level's top left corner sits on the world origin
+    TopLeft,
+    /// The level is centred on the world origin
+    #[default]
+    Centre,
+}
+
+// LDtk 0,0 is the top left, moving +x,+y goes right and down
+// Bevy 0,0 is the center of the screen, moving +x,+y goes right and up
+// Need to offset, scale and flip y
+/// Converts between LDtk pixels, LDtk grid cells and world positions for one level
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MapCoords {
+    /// Size of the level in LDtk pixels
+    pub px_size: Vec2,
+    /// Size of a grid cell in LDtk pixels
+    pub grid_size: i32,
+    /// World units per LDtk pixel
+    pub scale: f32,
+    pub origin: MapOrigin,
+}
+
+impl MapCoords {
+    fn origin_px(&self) -> Vec2 {
+        match self.origin {
+            MapOrigin::TopLeft => Vec2::ZERO,
+            MapOrigin::Centre => self.px_size / 2.,
+        }
+    }
+
+    /// Size of a grid cell in world units
+    pub fn tile_size(&self) -> Vec2 {
+        Vec2::splat(self.grid_size as f32 * self.scale)
+    }
+
+    /// Number of grid cells across and down the level
+    pub fn grid_dimensions(&self) -> IVec2 {
+        (self.px_size / self.grid_size as f32).ceil().as_i32()
+    }
+
+    pub fn px_to_world(&self, px: Vec2, z: f32) -> Vec3 {
+        let world = (px - self.origin_px()) * self.scale;
+        Vec3::new(world.x, -world.y, z)
+    }
+
+    pub fn world_to_px(&self, world: Vec3) -> Vec2 {
+        Vec2::new(world.x, -world.y) / self.scale + self.origin_px()
+    }
+
+    /// Grid cell containing a world position, may be outside the level
+    pub fn world_to_grid(&self, world: Vec3) -> IVec2 {
+        (self.world_to_px(world) / self.grid_size as f32)
+            .floor()
+            .as_i32()
+    }
+
+    /// World position of the centre of a grid cell
+    pub fn grid_to_world(&self, cell: IVec2, z: f32) -> Vec3 {
+        let px = (cell.as_f32() + Vec2::splat(0.5)) * self.grid_size as f32;
+        self.px_to_world(px, z)
+    }
+
+    /// Snaps a world position to the centre of the tile it is on, keeping its depth
+    pub fn world_to_tile(&self, world: Vec3) -> Vec3 {
+        self.grid_to_world(self.world_to_grid(world), world.z)
+    }
+
+    pub fn in_bounds(&self, cell: IVec2) -> bool {
+        let dimensions = self.grid_dimensions();
+        cell.x >= 0 && cell.y >= 0 && cell.x < dimensions.x && cell.y < dimensions.y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coords(origin: MapOrigin, scale: f32) -> MapCoords {
+        MapCoords {
+            px_size: Vec2::new(256., 128.),
+            grid_size: 16,
+            scale,
+            origin,
+        }
+    }
+
+    #[test]
+    fn top_left_origin_flips_y() {
+        let coords = coords(MapOrigin::TopLeft, 1.);
+        assert_eq!(
+            coords.px_to_world(Vec2::new(32., 48.), 5.),
+            Vec3::new(32., -48., 5.)
+        );
+    }
+
+    #[test]
+    fn centre_origin_centres_the_level() {
+        let coords = coords(MapOrigin::Centre, 1.);
+        assert_eq!(coords.px_to_world(Vec2::new(128., 64.), 0.), Vec3::ZERO);
+        assert_eq!(
+            coords.px_to_world(Vec2::ZERO, 0.),
+            Vec3::new(-128., 64., 0.)
+        );
+    }
+
+    #[test]
+    fn scale_applies_to_positions_and_tiles() {
+        let coords = coords(MapOrigin::TopLeft, 0.5);
+        assert_eq!(
+            coords.px_to_world(Vec2::new(32., 16.), 0.),
+            Vec3::new(16., -8., 0.)
+        );
+        assert_eq!(coords.tile_size(), Vec2::splat(8.));
+    }
+
+    #[test]
+    fn px_round_trips() {
+        for origin in [MapOrigin::TopLeft, MapOrigin::Centre].iter() {
+            let coords = coords(*origin, 0.25);
+            let px = Vec2::new(37., 91.);
+            assert_eq!(coords.world_to_px(coords.px_to_world(px, 3.)), px);
+        }
+    }
+
+    #[test]
+    fn world_to_grid_finds_the_containing_cell() {
+        let coords = coords(MapOrigin::Centre, 2.);
+        for cell in [IVec2::new(0, 0), IVec2::new(15, 7), IVec2::new(4, 3)].iter() {
+            assert_eq!(coords.world_to_grid(coords.grid_to_world(*cell, 0.)), *cell);
+        }
+        // Anywhere inside the cell counts, not just its centre
+        let corner = coords.px_to_world(Vec2::new(16.1, 31.9), 0.);
+        assert_eq!(coords.world_to_grid(corner), IVec2::new(1, 1));
+    }
+
+    #[test]
+    fn positions_outside_the_level_are_out_of_bounds() {
+        let coords = coords(MapOrigin::TopLeft, 1.);
+        assert_eq!(coords.grid_dimensions(), IVec2::new(16, 8));
+        let outside = coords.world_to_grid(Vec3::new(-1., 1., 0.));
+        assert_eq!(outside, IVec2::new(-1, -1));
+        assert!(!coords.in_bounds(outside));
+        assert!(coords.in_bounds(IVec2::new(15, 7)));
+        assert!(!coords.in_bounds(IVec2::new(16, 7)));
+    }
+
+    #[test]
+    fn world_to_tile_snaps_to_the_cell_centre() {
+        let coords = coords(MapOrigin::TopLeft, 1.);
+        assert_eq!(
+            coords.world_to_tile(Vec3::new(3., -30., 7.)),
+            Vec3::new(8., -24., 7.)
+        );
+    }
 }
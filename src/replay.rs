@@ -0,0 +1,433 @@
+use crate::input::{
+    ActionsLabel, CursorPosition, Gamepads, InputBindings, TrackCursorLabel, TrackGamepadsLabel,
+};
+use crate::map::tile_grid::TileGrid;
+use crate::ticks::{GameTicks, GameTime, GameTimeLabel};
+use bevy::app::AppExit;
+use bevy::input::gamepad::{Gamepad, GamepadAxis, GamepadButton};
+use bevy::input::{ElementState, InputSystem};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A recorded session: how it started and the input of every frame.
+/// Input is kept as the devices gave it, the bindings it was recorded with turn it into
+/// actions on playback.
+/// Frames are counted from the first frame the level is in place, so loading times don't matter
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub map: String,
+    /// The `--level` the session started on, the map's first level when None
+    #[serde(default)]
+    pub level: Option<String>,
+    /// The bindings in effect while recording, they replace the player's during playback
+    #[serde(default)]
+    pub bindings: InputBindings,
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, map: String) -> Self {
+        Replay {
+            seed,
+            map,
+            level: None,
+            bindings: InputBindings::default(),
+            frames: vec![],
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+/// Every axis a gamepad can have, `Axis` has no way to list the ones that were set
+const GAMEPAD_AXES: [GamepadAxisType; 8] = [
+    GamepadAxisType::LeftStickX,
+    GamepadAxisType::LeftStickY,
+    GamepadAxisType::LeftZ,
+    GamepadAxisType::RightStickX,
+    GamepadAxisType::RightStickY,
+    GamepadAxisType::RightZ,
+    GamepadAxisType::DPadX,
+    GamepadAxisType::DPadY,
+];
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct ReplayFrame {
+    /// How long the frame lasted for gameplay
+    pub delta: Duration,
+    pub keys: Buttons<KeyCode>,
+    pub mouse: Buttons<MouseButton>,
+    /// The gamepads plugged in, in the order they were connected
+    #[serde(default)]
+    pub gamepads: Vec<Gamepad>,
+    #[serde(default)]
+    pub gamepad_buttons: Buttons<GamepadButton>,
+    /// Positions of the gamepad axes that were set
+    #[serde(default)]
+    pub gamepad_axes: Vec<(GamepadAxis, f32)>,
+    /// Where the cursor pointed in the world
+    pub cursor: Option<[f32; 2]>,
+}
+
+/// The state of an `Input` during one frame
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Buttons<T> {
+    pub pressed: Vec<T>,
+    pub just_pressed: Vec<T>,
+    pub just_released: Vec<T>,
+}
+
+impl<T> Default for Buttons<T> {
+    fn default() -> Self {
+        Buttons {
+            pressed: vec![],
+            just_pressed: vec![],
+            just_released: vec![],
+        }
+    }
+}
+
+impl<T: Copy + Eq + Hash> Buttons<T> {
+    pub fn capture(input: &Input<T>) -> Self {
+        Buttons {
+            pressed: input.get_pressed().copied().collect(),
+            just_pressed: input.get_just_pressed().copied().collect(),
+            just_released: input.get_just_released().copied().collect(),
+        }
+    }
+
+    /// The press and release events that give an `Input` this frame's state
+    pub fn events(&self) -> Vec<(T, ElementState)> {
+        // Released and pressed again within the frame, the release has to come first
+        let repressed =
+            |button: &T| self.just_released.contains(button) && self.pressed.contains(button);
+
+        let mut events = vec![];
+        for button in self.just_pressed.iter() {
+            if repressed(button) {
+                events.push((*button, ElementState::Released));
+            }
+            events.push((*button, ElementState::Pressed));
+        }
+        for button in self.just_released.iter() {
+            if !(repressed(button) && self.just_pressed.contains(button)) {
+                events.push((*button, ElementState::Released));
+            }
+        }
+        events
+    }
+
+    /// Presses and releases the buttons of `input`, updated for the new frame, into this state
+    pub fn apply(&self, input: &mut Input<T>) {
+        for (button, state) in self.events() {
+            match state {
+                ElementState::Pressed => input.press(button),
+                ElementState::Released => input.release(button),
+            }
+        }
+    }
+
+    /// Buttons that were already held down when the frame started
+    fn held_before(&self) -> impl Iterator<Item = T> + '_ {
+        let held = self
+            .pressed
+            .iter()
+            .filter(move |button| !self.just_pressed.contains(button));
+        // Released during the frame, unless they were only tapped
+        let released = self.just_released.iter().filter(move |button| {
+            self.pressed.contains(button) || !self.just_pressed.contains(button)
+        });
+        held.chain(released).copied()
+    }
+
+    /// Puts `input` in this state, whatever it held before
+    pub fn restore(&self, input: &mut Input<T>) {
+        *input = Input::default();
+        for button in self.held_before() {
+            input.press(button);
+        }
+        input.update();
+        self.apply(input);
+    }
+}
+
+/// Records every frame while present, the replay is written to `path` when the game exits
+pub struct Recorder {
+    pub path: PathBuf,
+    pub replay: Replay,
+    recording: bool,
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf, replay: Replay) -> Self {
+        Recorder {
+            path,
+            replay,
+            recording: false,
+        }
+    }
+}
+
+/// Feeds a replay's frames to the game in place of the real input while present
+pub struct Playback {
+    pub replay: Replay,
+    frame: usize,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Playback { replay, frame: 0 }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.replay.frames.len()
+    }
+
+    /// The frame played last
+    pub fn current(&self) -> Option<&ReplayFrame> {
+        self.frame
+            .checked_sub(1)
+            .and_then(|frame| self.replay.frames.get(frame))
+    }
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(
+            CoreStage::First,
+            play_frame
+                .system()
+                .after(GameTimeLabel)
+                .after(TrackCursorLabel),
+        )
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            play_devices
+                .system()
+                .after(InputSystem)
+                .after(TrackGamepadsLabel)
+                .before(ActionsLabel),
+        )
+        .add_system_to_stage(CoreStage::First, start_recording.system())
+        .add_system_to_stage(CoreStage::Last, record_frame.system().label(RecordLabel))
+        .add_system_to_stage(
+            CoreStage::Last,
+            save_recording_on_exit.system().after(RecordLabel),
+        );
+    }
+}
+
+#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
+struct RecordLabel;
+
+/// Starts recording once the level is in place, with no tick half done from loading
+fn start_recording(
+    grid: Option<Res<TileGrid>>,
+    recorder: Option<ResMut<Recorder>>,
+    bindings: Res<InputBindings>,
+    mut ticks: ResMut<GameTicks>,
+) {
+    if let (Some(_), Some(mut recorder)) = (grid, recorder) {
+        if !recorder.recording {
+            recorder.recording = true;
+            recorder.replay.bindings = bindings.clone();
+            ticks.reset();
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn record_frame(
+    recorder: Option<ResMut<Recorder>>,
+    time: Res<GameTime>,
+    cursor: Res<CursorPosition>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+) {
+    let mut recorder = match recorder {
+        Some(recorder) if recorder.recording => recorder,
+        _ => return,
+    };
+    let gamepad_axes = gamepads
+        .0
+        .iter()
+        .flat_map(|gamepad| {
+            GAMEPAD_AXES
+                .iter()
+                .map(move |axis| GamepadAxis(*gamepad, *axis))
+        })
+        .filter_map(|axis| axes.get(axis).map(|value| (axis, value)))
+        .collect();
+    recorder.replay.frames.push(ReplayFrame {
+        delta: time.delta(),
+        keys: Buttons::capture(&keys),
+        mouse: Buttons::capture(&mouse),
+        gamepads: gamepads.0.clone(),
+        gamepad_buttons: Buttons::capture(&buttons),
+        gamepad_axes,
+        cursor: cursor.0.map(|cursor| cursor.into()),
+    });
+}
+
+fn save_recording_on_exit(recorder: Option<Res<Recorder>>, mut exits: EventReader<AppExit>) {
+    let recorder = match recorder {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    if exits.iter().next().is_none() {
+        return;
+    }
+
+    match recorder.replay.save(&recorder.path) {
+        Ok(()) => info!(
+            "Saved {} frames to {}",
+            recorder.replay.frames.len(),
+            recorder.path.display()
+        ),
+        Err(err) => error!("Could not save {}: {}", recorder.path.display(), err),
+    }
+}
+
+/// Starts the next frame of the replay: its length, where the cursor was and the bindings it
+/// was recorded with. Its devices are played by `play_devices`
+pub fn play_frame(
+    grid: Option<Res<TileGrid>>,
+    playback: Option<ResMut<Playback>>,
+    mut time: ResMut<GameTime>,
+    mut ticks: ResMut<GameTicks>,
+    mut bindings: ResMut<InputBindings>,
+    mut cursor: ResMut<CursorPosition>,
+) {
+    let mut playback = match playback {
+        Some(playback) if grid.is_some() && !playback.finished() => playback,
+        _ => return,
+    };
+    // Ticks land on the same frames as they did when recording, whatever loading took
+    if playback.frame == 0 {
+        ticks.reset();
+    }
+    if *bindings != playback.replay.bindings {
+        *bindings = playback.replay.bindings.clone();
+    }
+
+    let frame = playback.replay.frames[playback.frame].clone();
+    playback.frame += 1;
+    if playback.finished() {
+        info!("Replay finished");
+    }
+
+    time.set_delta(frame.delta);
+    cursor.0 = frame.cursor.map(Vec2::from);
+}
+
+/// Puts the devices in the state they were in during the frame being replayed, over whatever
+/// the real ones did. Actions are read from them through the bindings, like when recording
+pub fn play_devices(
+    playback: Option<Res<Playback>>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut mouse: ResMut<Input<MouseButton>>,
+    mut gamepads: ResMut<Gamepads>,
+    mut buttons: ResMut<Input<GamepadButton>>,
+    mut axes: ResMut<Axis<GamepadAxis>>,
+) {
+    // Changed when `play_frame` started one of its frames
+    let frame = match playback {
+        Some(playback) if playback.is_changed() => match playback.current() {
+            Some(frame) => frame.clone(),
+            None => return,
+        },
+        _ => return,
+    };
+
+    frame.keys.restore(&mut keys);
+    frame.mouse.restore(&mut mouse);
+    gamepads.0 = frame.gamepads;
+    frame.gamepad_buttons.restore(&mut buttons);
+    *axes = Axis::default();
+    for (axis, value) in frame.gamepad_axes {
+        axes.set(axis, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut keys: Vec<KeyCode>) -> Vec<KeyCode> {
+        keys.sort_by_key(|key| *key as u32);
+        keys
+    }
+
+    #[test]
+    fn events_recreate_the_recorded_state() {
+        let mut recorded = Input::<KeyCode>::default();
+        let mut replayed = Input::<KeyCode>::default();
+        let frames: Vec<fn(&mut Input<KeyCode>)> = vec![
+            |input| input.press(KeyCode::W),
+            |_| {},
+            |input| {
+                // Tapped within a single frame
+                input.press(KeyCode::Space);
+                input.release(KeyCode::Space);
+            },
+            |input| {
+                input.release(KeyCode::W);
+                input.press(KeyCode::W);
+            },
+            |input| input.release(KeyCode::W),
+        ];
+
+        let mut restored = Input::<KeyCode>::default();
+        for frame in frames {
+            recorded.update();
+            frame(&mut recorded);
+            replayed.update();
+            Buttons::capture(&recorded).apply(&mut replayed);
+            // Whatever the real keyboard did in the meantime
+            restored.press(KeyCode::Escape);
+            Buttons::capture(&recorded).restore(&mut restored);
+
+            let recorded = Buttons::capture(&recorded);
+            for replayed in [&replayed, &restored].iter() {
+                let replayed = Buttons::capture(replayed);
+                assert_eq!(sorted(recorded.pressed.clone()), sorted(replayed.pressed));
+                assert_eq!(
+                    sorted(recorded.just_pressed.clone()),
+                    sorted(replayed.just_pressed)
+                );
+                assert_eq!(
+                    sorted(recorded.just_released.clone()),
+                    sorted(replayed.just_released)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn replays_survive_a_round_trip() {
+        let mut replay = Replay::new(7, "map.ldtk".to_string());
+        replay.frames.push(ReplayFrame {
+            delta: Duration::from_millis(16),
+            cursor: Some([1., 2.]),
+            ..Default::default()
+        });
+        let json = serde_json::to_string(&replay).unwrap();
+        assert_eq!(serde_json::from_str::<Replay>(&json).unwrap(), replay);
+    }
+}
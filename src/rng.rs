@@ -0,0 +1,102 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+/// Source of all gameplay randomness, so a run can be played again from its seed.
+/// Each system draws from its own named stream, so adding draws to one system doesn't
+/// change what the others get. The generator is named rather than `StdRng`, which may change
+/// between rand versions and would break saved seeds and replays
+pub struct GameRng {
+    seed: u64,
+    streams: HashMap<&'static str, ChaCha8Rng>,
+}
+
+/// A random seed, for runs that don't need repeating
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng::new(rand::random())
+    }
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng {
+            seed,
+            streams: HashMap::new(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The generator for one system, created from the seed the first time it is asked for
+    pub fn stream(&mut self, name: &'static str) -> &mut ChaCha8Rng {
+        let seed = self.seed;
+        self.streams
+            .entry(name)
+            .or_insert_with(|| ChaCha8Rng::seed_from_u64(stream_seed(seed, name)))
+    }
+
+    /// A generator of its own for a new entity, seeded from the `name` stream
+    pub fn fork(&mut self, name: &'static str) -> EntityRng {
+        EntityRng(ChaCha8Rng::seed_from_u64(self.stream(name).gen()))
+    }
+}
+
+/// Mixes the stream name into the seed with FNV-1a, which unlike the std hasher is
+/// guaranteed to stay the same between builds
+fn stream_seed(seed: u64, name: &str) -> u64 {
+    name.bytes()
+        .fold(seed ^ 0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Per-entity generator, so the order entities are processed in doesn't change their draws
+pub struct EntityRng(pub ChaCha8Rng);
+
+impl Deref for EntityRng {
+    type Target = ChaCha8Rng;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for EntityRng {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draws(rng: &mut impl Rng) -> Vec<u32> {
+        (0..8).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn same_seed_same_draws() {
+        let mut a = GameRng::new(42);
+        let mut b = GameRng::new(42);
+        assert_eq!(draws(a.stream("enemy")), draws(b.stream("enemy")));
+        assert_eq!(draws(&mut *a.fork("enemy")), draws(&mut *b.fork("enemy")));
+        assert_ne!(
+            draws(GameRng::new(42).stream("enemy")),
+            draws(GameRng::new(43).stream("enemy"))
+        );
+    }
+
+    #[test]
+    fn streams_dont_affect_each_other() {
+        let mut a = GameRng::new(7);
+        let mut b = GameRng::new(7);
+        draws(a.stream("projectile"));
+        assert_eq!(draws(a.stream("enemy")), draws(b.stream("enemy")));
+        assert_ne!(draws(b.stream("projectile")), draws(b.stream("enemy")));
+    }
+}
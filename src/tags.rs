@@ -1,18 +1,36 @@
 pub struct Player;
 pub struct MainCamera;
 
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum WorldType {
     Air,
     Wall,
-    Door,    //Todo replace with Entity
+    Door,    // Closed doors, kept up to date by the door entities
     Nothing, // Stub
+    Gold,
 }
 
+impl WorldType {
+    /// Which type wins when a cell or tile has several, walls beat everything
+    pub fn solidity(&self) -> u8 {
+        match self {
+            WorldType::Air => 0,
+            WorldType::Gold => 1,
+            WorldType::Nothing => 2,
+            WorldType::Door => 3,
+            WorldType::Wall => 4,
+        }
+    }
+}
+
+/// Accepts both the `Collideable` enum values and the older custom data strings
 pub fn world_type_from_str(s: &str) -> Option<WorldType> {
     match s {
         "Wall" => Some(WorldType::Wall),
-        "Floor" => Some(WorldType::Air),
+        "Air" | "Floor" => Some(WorldType::Air),
+        "Door" => Some(WorldType::Door),
+        "Nothing" => Some(WorldType::Nothing),
+        "Gold" => Some(WorldType::Gold),
         _ => None,
     }
 }
@@ -0,0 +1,117 @@
+use bevy::core::CoreSystem;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use heron::PhysicsSteps;
+use std::time::Duration;
+
+/// When the fixed step gameplay systems (AI, path following) run
+pub struct GameTicks {
+    /// Seconds between two ticks
+    pub step: f64,
+    /// Make every frame exactly one tick long whatever the clock says, so headless runs repeat
+    pub manual: bool,
+    accumulator: f64,
+    looping: bool,
+}
+
+impl GameTicks {
+    pub fn new(step: f64) -> Self {
+        GameTicks {
+            step,
+            manual: false,
+            accumulator: 0.,
+            looping: false,
+        }
+    }
+
+    pub fn manual() -> Self {
+        GameTicks {
+            manual: true,
+            ..Default::default()
+        }
+    }
+
+    /// Drops the time left over towards the next tick
+    pub fn reset(&mut self) {
+        self.accumulator = 0.;
+        self.looping = false;
+    }
+}
+
+impl Default for GameTicks {
+    fn default() -> Self {
+        GameTicks::new(0.05)
+    }
+}
+
+/// How long the current frame lasts for gameplay. Gameplay timers use this instead of `Time`,
+/// so manual runs and replays can decide how much time passes
+#[derive(Default)]
+pub struct GameTime {
+    delta: Duration,
+}
+
+impl GameTime {
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    pub fn set_delta(&mut self, delta: Duration) {
+        self.delta = delta;
+    }
+}
+
+#[derive(SystemLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct GameTimeLabel;
+
+pub struct GameTimePlugin;
+
+impl Plugin for GameTimePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<GameTicks>()
+            .init_resource::<GameTime>()
+            .add_system_to_stage(
+                CoreStage::First,
+                update_game_time
+                    .system()
+                    .label(GameTimeLabel)
+                    .after(CoreSystem::Time),
+            )
+            // After everything in `First` has had its say on the delta, replays included
+            .add_system_to_stage(CoreStage::PreUpdate, step_physics_by_game_time.system());
+    }
+}
+
+pub fn update_game_time(time: Res<Time>, ticks: Res<GameTicks>, mut game_time: ResMut<GameTime>) {
+    game_time.delta = if ticks.manual {
+        Duration::from_secs_f64(ticks.step)
+    } else {
+        time.delta()
+    };
+}
+
+/// Steps physics once a frame by `GameTime`, instead of by the clock, so it moves things
+/// exactly as far as it did when a replay was recorded
+pub fn step_physics_by_game_time(time: Res<GameTime>, mut steps: ResMut<PhysicsSteps>) {
+    *steps = PhysicsSteps::every_frame(time.delta());
+}
+
+/// Run criteria for the gameplay systems. Works like `FixedTimestep` on `GameTime`, catching
+/// up with several ticks in one frame when it has to
+pub fn run_game_tick(time: Res<GameTime>, mut ticks: ResMut<GameTicks>) -> ShouldRun {
+    if !ticks.looping {
+        ticks.accumulator += time.delta().as_secs_f64();
+    }
+    if ticks.accumulator >= ticks.step {
+        ticks.accumulator -= ticks.step;
+        ticks.looping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        ticks.looping = false;
+        ShouldRun::No
+    }
+}
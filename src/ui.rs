@@ -4,12 +4,18 @@ pub struct GameOverlayPlugin;
 
 impl Plugin for GameOverlayPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_system(setup.system())
+        app.init_resource::<Score>()
+            .add_startup_system(setup.system())
             .add_system(update_score_ui.system());
     }
 }
 
-pub struct Score(i32);
+/// Gold collected so far
+#[derive(Default)]
+pub struct Score(pub u32);
+
+/// Marks the text showing the score
+pub struct ScoreText;
 
 fn setup(mut c: Commands, asset_server: Res<AssetServer>) {
     c.spawn_bundle(UiCameraBundle::default());
@@ -28,11 +34,14 @@ fn setup(mut c: Commands, asset_server: Res<AssetServer>) {
         // transform: Default::default(),
         ..Default::default()
     })
-    .insert(Score(0));
+    .insert(ScoreText);
 }
 
-fn update_score_ui(mut q: Query<(&Score, &mut TextSection)>) {
-    for (score, mut text) in q.iter_mut() {
-        text.value = format!("Score: {}", score.0);
+fn update_score_ui(score: Res<Score>, mut q: Query<&mut Text, With<ScoreText>>) {
+    if !score.is_changed() {
+        return;
+    }
+    for mut text in q.iter_mut() {
+        text.sections[0].value = format!("Score: {}", score.0);
     }
 }
@@ -0,0 +1,225 @@
+use bevy::input::gamepad::Gamepad;
+use bevy::prelude::*;
+use heron::Velocity;
+use rusty_jam_08_21::entity_class::attack::EnemyAttack;
+use rusty_jam_08_21::entity_class::door::Door;
+use rusty_jam_08_21::entity_class::enemy::{Enemy, EnemyState};
+use rusty_jam_08_21::entity_class::gold::Gold;
+use rusty_jam_08_21::entity_class::health::{Damaged, Health};
+use rusty_jam_08_21::entity_class::patrol_path::PatrolPath;
+use rusty_jam_08_21::entity_class::projectile::Projectile;
+use rusty_jam_08_21::entity_class::vision::VisionCone;
+use rusty_jam_08_21::headless::Simulation;
+use rusty_jam_08_21::input::InputBindings;
+use rusty_jam_08_21::map::map_loader::MapTile;
+use rusty_jam_08_21::map::tile_grid::TileGrid;
+use rusty_jam_08_21::replay::Replay;
+use rusty_jam_08_21::tags::{Player, WorldType};
+
+fn simulation() -> Simulation {
+    Simulation::load("map.ldtk", 1)
+}
+
+#[test]
+fn loads_the_first_level() {
+    let mut sim = simulation();
+    assert!(sim.world().get_resource::<TileGrid>().is_some());
+    assert_eq!(sim.count::<Player>(), 1);
+    assert_eq!(sim.count::<Enemy>(), 2);
+    assert_eq!(sim.count::<Gold>(), 8);
+    assert_eq!(sim.count::<Door>(), 3);
+}
+
+#[test]
+fn wall_tiles_cover_their_whole_cell() {
+    let mut sim = simulation();
+    let world = sim.world();
+    let (size, centre) = world
+        .query::<(&MapTile, &Transform)>()
+        .iter(world)
+        .find(|(tile, _)| tile.world_type == WorldType::Wall)
+        .map(|(tile, transform)| (tile.size, transform.translation))
+        .expect("the level has no walls");
+
+    let grid = world.get_resource::<TileGrid>().unwrap();
+    // Every layer of map.ldtk is on a 64px grid
+    assert_eq!(grid.coords.tile_size(), Vec2::splat(64.));
+    assert_eq!(size, grid.coords.tile_size());
+    for corner in [
+        Vec2::new(-1., -1.),
+        Vec2::new(-1., 1.),
+        Vec2::new(1., -1.),
+        Vec2::ONE,
+    ]
+    .iter()
+    {
+        let inside = centre + (*corner * (size / 2. - Vec2::ONE)).extend(0.);
+        assert!(grid.is_solid(grid.coords.world_to_grid(inside)));
+    }
+}
+
+#[test]
+fn patrol_waypoints_land_on_their_cells() {
+    let mut sim = simulation();
+    // Paths are placed the frame after they are indexed
+    sim.step(2);
+    let world = sim.world();
+    let paths = world
+        .query::<&PatrolPath>()
+        .iter(world)
+        .map(|path| path.waypoints.clone())
+        .collect::<Vec<_>>();
+
+    // The path through cells 2,10 4,10 4,7 2,7 of the 64px entity layer, on a centred
+    // 1024px level
+    let expected = vec![
+        Vec2::new(-352., -160.),
+        Vec2::new(-224., -160.),
+        Vec2::new(-224., 32.),
+        Vec2::new(-352., 32.),
+    ];
+    assert!(paths.contains(&expected), "{:?}", paths);
+}
+
+#[test]
+fn damage_drains_health_until_despawned() {
+    let mut sim = simulation();
+    let player = sim.player().unwrap();
+    // Health is added the frame after creatures spawn
+    sim.step(1);
+
+    sim.send(Damaged {
+        damage: 30,
+        entity: player,
+    });
+    sim.step(1);
+    assert_eq!(sim.world().get::<Health>(player).unwrap().value(), 70);
+
+    sim.send(Damaged {
+        damage: 70,
+        entity: player,
+    });
+    sim.step(2);
+    assert!(sim.world().get_entity(player).is_none());
+}
+
+#[test]
+fn players_move_with_the_keyboard() {
+    let mut sim = simulation();
+    let player = sim.player().unwrap();
+
+    sim.press_key(KeyCode::D);
+    sim.step(5);
+    assert!(sim.world().get::<Velocity>(player).unwrap().linear.x > 0.);
+
+    sim.release_key(KeyCode::D);
+    sim.press_key(KeyCode::A);
+    sim.step(20);
+    assert!(sim.world().get::<Velocity>(player).unwrap().linear.x < 0.);
+}
+
+#[test]
+fn players_move_with_a_gamepad() {
+    let mut sim = simulation();
+    let player = sim.player().unwrap();
+    let gamepad = Gamepad(0);
+
+    sim.connect_gamepad(gamepad);
+    sim.step(1);
+    sim.move_gamepad_axis(gamepad, GamepadAxisType::LeftStickY, -1.);
+    sim.step(5);
+    assert!(sim.world().get::<Velocity>(player).unwrap().linear.y < 0.);
+}
+
+#[test]
+fn enemies_attack_players_in_range() {
+    let mut sim = simulation();
+    let player = sim.player().unwrap();
+    let enemy = sim.entities::<Enemy>()[0];
+
+    // Put the enemy right next to the player, without having to face them first
+    let next_to_player = sim.world().get::<Transform>(player).unwrap().translation + Vec3::X * 40.;
+    sim.world().entity_mut(enemy).remove::<VisionCone>();
+    sim.world().get_mut::<Transform>(enemy).unwrap().translation = next_to_player;
+    sim.step(1);
+
+    let world = sim.world();
+    assert!(matches!(
+        world.get::<Enemy>(enemy).unwrap().state,
+        EnemyState::Attack
+    ));
+    assert_eq!(
+        world.get::<EnemyAttack>(enemy).unwrap().target,
+        Some(player)
+    );
+
+    // Out of melee reach, down the open row the player starts on, so only projectiles can hurt
+    let in_fire_range = next_to_player + Vec3::X * 88.;
+    world.get_mut::<EnemyAttack>(enemy).unwrap().melee_range = 0.;
+    world.get_mut::<Transform>(enemy).unwrap().translation = in_fire_range;
+    // Long enough for the attack cooldown and the projectile's flight
+    sim.step(60);
+    assert!(sim.world().get::<Health>(player).unwrap().value() < 100);
+}
+
+#[test]
+fn replays_repeat_the_recorded_session() {
+    let mut sim = Simulation::record("map.ldtk", 1);
+    let player = sim.player().unwrap();
+
+    sim.press_key(KeyCode::D);
+    sim.step(10);
+    sim.press_key(KeyCode::S);
+    let aim = sim
+        .world()
+        .get::<Transform>(player)
+        .unwrap()
+        .translation
+        .truncate()
+        + Vec2::X * 100.;
+    sim.set_cursor(Some(aim));
+    sim.press_mouse(MouseButton::Left);
+    sim.step(1);
+    sim.release_mouse(MouseButton::Left);
+    sim.release_key(KeyCode::D);
+    sim.step(10);
+    sim.release_key(KeyCode::S);
+    sim.step(5);
+
+    // Through a file, like `--record` and `--replay` do
+    let path = std::env::temp_dir().join(format!(
+        "rusty_jam_replays_repeat_the_recorded_session_{}.json",
+        std::process::id()
+    ));
+    sim.recording().save(&path).unwrap();
+    let replay = Replay::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut replayed = Simulation::replay(replay);
+    // Rebinding after recording must not change what the replay does
+    replayed
+        .world()
+        .get_resource_mut::<InputBindings>()
+        .unwrap()
+        .actions
+        .clear();
+    replayed.play_to_end();
+
+    let replayed_player = replayed.player().unwrap();
+    assert_eq!(
+        sim.world().get::<Transform>(player).unwrap().translation,
+        replayed
+            .world()
+            .get::<Transform>(replayed_player)
+            .unwrap()
+            .translation
+    );
+    assert_eq!(
+        sim.world().get::<Velocity>(player).unwrap().linear,
+        replayed
+            .world()
+            .get::<Velocity>(replayed_player)
+            .unwrap()
+            .linear
+    );
+    assert_eq!(sim.count::<Projectile>(), replayed.count::<Projectile>());
+}